@@ -5,45 +5,191 @@
 // Supports both flexible staking and locked staking with bonus multipliers.
 
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, TransferChecked};
+use anchor_spl::metadata::{Metadata, MetadataAccount};
+use anchor_spl::token_interface::{
+    self, Burn, CloseAccount, Mint, MintTo, TokenAccount, TokenInterface, TransferChecked,
+};
 
 // Program ID - Updated to avoid corrupted accounts from v1.0
 // Deployed: 2025-12-30
 declare_id!("6Gah3kZjZ9f9q4CUmF8BAc7ZXuACFDbLFWNTmWGS5CoZ");
 
+/// Fixed-point scale for `Pool.acc_reward_per_share`
+pub const SHARE_PRECISION: u128 = 1_000_000_000_000;
+
+/// Basis-point scale for multipliers, fees and penalties (10000 = 100% / 1x)
+pub const BPS_SCALE: u64 = 10_000;
+
+/// Maximum number of lock tiers a pool can configure
+pub const MAX_LOCK_TIERS: usize = 4;
+
+/// Maximum number of additional reward streams a pool can configure
+pub const MAX_REWARD_STREAMS: usize = 4;
+
+/// Seconds in a (non-leap) year, used to annualize reward rates
+pub const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+/// Maximum number of TVL breakpoints in a pool's reward curve
+pub const MAX_REWARD_BREAKPOINTS: usize = 4;
+
+/// Longest lock a tier may configure (4 years)
+pub const MAX_LOCK_DURATION: u64 = 4 * SECONDS_PER_YEAR;
+
+/// Number of stake snapshots kept per user (oldest is overwritten)
+pub const MAX_STAKE_SNAPSHOTS: usize = 4;
+
+/// Current `User` account schema version
+pub const USER_VERSION: u8 = 15;
+
+/// Current `Pool` account schema version. Pools and the global state written
+/// by the original program have an incompatible layout; deployments carrying
+/// them must be redeployed under a new program id (see README).
+pub const POOL_VERSION: u8 = 1;
+
 #[program]
 pub mod wave_stake {
     use super::*;
 
-    /// Initialize the global staking state
-    pub fn initialize(ctx: Context<Initialize>, authority: Pubkey) -> Result<()> {
+    /// Initialize the global staking state. Can only succeed once: a set
+    /// authority marks the state as initialized.
+    pub fn initialize(ctx: Context<Initialize>, authority: Pubkey, max_pools: u64) -> Result<()> {
         let global_state = &mut ctx.accounts.global_state;
+        require!(
+            global_state.authority == Pubkey::default(),
+            ErrorCode::AlreadyInitialized
+        );
+        require_keys_neq!(authority, Pubkey::default(), ErrorCode::Unauthorized);
+
         global_state.bump = ctx.bumps.global_state;
         global_state.authority = authority;
+        global_state.pending_authority = Pubkey::default();
         global_state.pool_count = 0;
+        global_state.emergency_shutdown = false;
+        global_state.max_pools = max_pools;
+        global_state.total_value_locked = 0;
+        global_state.total_rewards_distributed = 0;
         msg!("Global state initialized with authority: {}", authority);
         Ok(())
     }
 
+    /// Propose a new global authority (current authority only)
+    pub fn propose_authority(ctx: Context<ProposeAuthority>, new_authority: Pubkey) -> Result<()> {
+        let global_state = &mut ctx.accounts.global_state;
+        global_state.pending_authority = new_authority;
+        msg!("Proposed new global authority: {}", new_authority);
+        Ok(())
+    }
+
+    /// Accept a pending global authority proposal (pending authority only)
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let global_state = &mut ctx.accounts.global_state;
+        require!(
+            global_state.pending_authority != Pubkey::default(),
+            ErrorCode::Unauthorized
+        );
+        require_keys_eq!(
+            ctx.accounts.new_authority.key(),
+            global_state.pending_authority,
+            ErrorCode::Unauthorized
+        );
+
+        global_state.authority = global_state.pending_authority;
+        global_state.pending_authority = Pubkey::default();
+        msg!("Global authority transferred to: {}", global_state.authority);
+        Ok(())
+    }
+
+    /// Toggle the protocol-wide kill switch (global authority only). While set,
+    /// new deposits are rejected but every exit path stays open.
+    pub fn set_emergency_shutdown(ctx: Context<SetEmergencyShutdown>, value: bool) -> Result<()> {
+        let global_state = &mut ctx.accounts.global_state;
+        global_state.emergency_shutdown = value;
+        msg!("Emergency shutdown set to: {}", value);
+        Ok(())
+    }
+
+    /// Change the maximum number of live pools (global authority only)
+    pub fn set_max_pools(ctx: Context<SetMaxPools>, max_pools: u64) -> Result<()> {
+        let global_state = &mut ctx.accounts.global_state;
+        global_state.max_pools = max_pools;
+        msg!("Max pools set to: {}", max_pools);
+        Ok(())
+    }
+
     /// Create user account (must be called before first stake)
-    pub fn create_user_account(ctx: Context<CreateUserAccount>) -> Result<()> {
-        let user = &mut ctx.accounts.user;
-        let pool = &ctx.accounts.pool;
+    /// `referrer` (optional) earns the pool's referral bonus on this user's claims;
+    /// `label` (optional) is a free-form tag for the position.
+    /// `position_index` lets one wallet hold several positions in a pool, each
+    /// with its own lock; index 0 is the wallet's original position.
+    pub fn create_user_account(
+        ctx: Context<CreateUserAccount>,
+        referrer: Option<Pubkey>,
+        label: Option<[u8; 16]>,
+        position_index: u8,
+    ) -> Result<()> {
+        init_user(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.user,
+            ctx.accounts.authority.key(),
+            referrer.unwrap_or_default(),
+            label.unwrap_or_default(),
+            position_index,
+            ctx.bumps.user,
+        )
+    }
+
+    /// Grow the signer's position at `position_index` if it was created before
+    /// newer fields were added. Idempotent: accounts already at `USER_VERSION`
+    /// are left untouched.
+    pub fn migrate_user(ctx: Context<MigrateUser>, position_index: u8) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
         let clock = Clock::get()?;
+        update_pool_rewards(pool, clock.unix_timestamp)?;
 
-        user.bump = ctx.bumps.user;
-        user.amount = 0;
-        user.lock_type = 0;
-        user.lock_start_timestamp = 0;
-        user.lock_end_timestamp = 0;
-        user.bonus_multiplier = 10000;
-        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        // The account has already been grown (and zero-filled) by the realloc constraint
+        let user_info = ctx.accounts.user.to_account_info();
+        let mut data = user_info.try_borrow_mut_data()?;
+        let mut user = User::try_deserialize(&mut &data[..])?;
+
+        if user.version >= USER_VERSION {
+            msg!("User account already at version {}", user.version);
+            return Ok(());
+        }
 
-        msg!("User account created for pool: {}", String::from_utf8_lossy(&pool.pool_id));
+        // Start reward accounting from the current accumulators so legacy
+        // positions don't claim emissions from before the upgrade
+        if user.version < 1 {
+            user.reward_debt = reward_debt_for(pool, user.amount)?;
+            user.unclaimed_rewards = 0;
+            user.last_reward_claim_timestamp = clock.unix_timestamp;
+        }
+        sync_reward_stream_debts(pool, &mut user)?;
+        if user.version < 8 {
+            user.weighted_stake = 0;
+        }
+        // Weights gained the loyalty and NFT bonuses in version 15
+        if user.version < 15 {
+            reweigh_stake(pool, &mut user, clock.unix_timestamp)?;
+        }
+        if user.version < 11 {
+            user.owner = ctx.accounts.authority.key();
+        }
+        user.position_index = position_index;
+        // Older positions never recorded their LST; credit what the stake is
+        // worth at the current rate
+        if user.version < 15 {
+            user.lst_minted = lst_for_deposit(pool, ctx.accounts.lst_mint.supply, user.amount)?;
+        }
+        user.pool = pool.key();
+        user.version = USER_VERSION;
+        user.try_serialize(&mut &mut data[..])?;
+
+        msg!("User account migrated to version {}", USER_VERSION);
         Ok(())
     }
 
-    /// Create a new staking pool
+    /// Create a new staking pool. A zero `reward_per_second` is rejected unless
+    /// `allow_zero_reward` marks the pool as intentionally reward-free.
     pub fn create_pool(
         ctx: Context<CreatePool>,
         pool_id: [u8; 32],
@@ -51,23 +197,54 @@ pub mod wave_stake {
         lst_mint: Pubkey,
         reward_mint: Pubkey,
         reward_per_second: u64,
-        lock_duration: u64,
-        lock_bonus_percentage: u16,
+        lock_tiers: Vec<LockTier>,
+        min_stake: u64,
+        max_total_staked: u64,
+        reward_start_time: i64,
+        reward_end_time: i64,
+        allow_zero_reward: bool,
     ) -> Result<()> {
+        require_keys_eq!(ctx.accounts.stake_mint.key(), stake_mint, ErrorCode::InvalidMint);
+        require!(reward_per_second > 0 || allow_zero_reward, ErrorCode::ZeroRewardRate);
+        require_keys_eq!(ctx.accounts.lst_mint.key(), lst_mint, ErrorCode::InvalidMint);
+        require_keys_eq!(ctx.accounts.reward_mint.key(), reward_mint, ErrorCode::InvalidMint);
+        require!(
+            ctx.accounts.global_state.pool_count < ctx.accounts.global_state.max_pools,
+            ErrorCode::MaxPoolsReached
+        );
+
         let pool = &mut ctx.accounts.pool;
         pool.bump = ctx.bumps.pool;
+        pool.version = POOL_VERSION;
         pool.pool_id = pool_id;
         pool.stake_mint = stake_mint;
         pool.lst_mint = lst_mint;
         pool.reward_mint = reward_mint;
         pool.reward_per_second = reward_per_second;
-        pool.lock_duration = lock_duration;
-        pool.lock_bonus_percentage = lock_bonus_percentage;
+        pool.stake_decimals = ctx.accounts.stake_mint.decimals;
+        pool.reward_decimals = ctx.accounts.reward_mint.decimals;
+        pool.set_lock_tiers(&lock_tiers)?;
         pool.total_staked = 0;
+        pool.total_weighted_stake = 0;
         pool.total_reward_distributed = 0;
-        pool.last_update_timestamp = Clock::get()?.unix_timestamp;
+        pool.acc_reward_per_share = 0;
+        pool.total_reward_funded = 0;
+        pool.early_withdraw_penalty_bps = 0;
+        pool.unstake_cooldown = 0;
+        pool.total_unstake_pending = 0;
+        pool.user_count = 0;
+        pool.max_users = 0;
+        pool.deposit_fee_bps = 0;
+        pool.treasury = Pubkey::default();
+        pool.min_stake = min_stake;
+        pool.max_total_staked = max_total_staked;
+        pool.set_reward_window(reward_start_time, reward_end_time)?;
+        let clock = Clock::get()?;
+        pool.last_update_timestamp = clock.unix_timestamp;
+        pool.last_update_slot = clock.slot;
         pool.authority = ctx.accounts.authority.key();
 
+        // Counted only once every check has passed
         let global_state = &mut ctx.accounts.global_state;
         global_state.pool_count += 1;
 
@@ -78,396 +255,4829 @@ pub mod wave_stake {
         Ok(())
     }
 
-    /// Stake tokens with optional lock period
-    /// lock_type: 0 = flexible, 1 = locked (30 days)
-    pub fn stake(ctx: Context<Stake>, amount: u64, lock_type: u8) -> Result<()> {
-        require!(amount > 0, ErrorCode::InvalidAmount);
+    /// Add an extra reward token emitted alongside the primary reward (authority only)
+    pub fn add_reward_stream(
+        ctx: Context<AddRewardStream>,
+        reward_mint: Pubkey,
+        reward_per_second: u64,
+    ) -> Result<()> {
+        require_keys_eq!(ctx.accounts.reward_mint.key(), reward_mint, ErrorCode::InvalidMint);
 
         let pool = &mut ctx.accounts.pool;
-        let user = &mut ctx.accounts.user;
-        let clock = Clock::get()?;
+        let index = pool.reward_stream_count as usize;
+        require!(index < MAX_REWARD_STREAMS, ErrorCode::TooManyRewardStreams);
 
-        // Calculate time elapsed and update pool rewards
-        let time_elapsed = (clock.unix_timestamp - pool.last_update_timestamp) as u64;
-        if time_elapsed > 0 && pool.total_staked > 0 {
-            let rewards_to_distribute = pool.reward_per_second
-                .checked_mul(time_elapsed)
-                .ok_or(ErrorCode::MathOverflow)?;
-            pool.total_reward_distributed = pool.total_reward_distributed
-                .checked_add(rewards_to_distribute)
-                .ok_or(ErrorCode::MathOverflow)?;
-        }
-        pool.last_update_timestamp = clock.unix_timestamp;
+        // Settle the existing streams so the new one starts accruing from now
+        update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
 
-        // Check if this is a new user account (amount will be 0 if uninitialized)
-        // Only set bump and lock type on first stake
-        let is_new_user = user.amount == 0;
+        pool.reward_streams[index] = RewardStream {
+            reward_mint,
+            reward_vault: ctx.accounts.reward_vault.key(),
+            reward_per_second,
+            acc_reward_per_share: 0,
+        };
+        pool.reward_stream_count += 1;
+
+        msg!(
+            "Added reward stream {} for mint {} at {} per second",
+            index,
+            reward_mint,
+            reward_per_second
+        );
+        Ok(())
+    }
 
-        user.amount = user.amount.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+    /// Stake tokens into one of the pool's lock tiers
+    /// tier_index: index into `pool.lock_tiers` (a zero-duration tier is flexible)
+    pub fn stake(ctx: Context<Stake>, amount: u64, tier_index: u8, auto_renew: bool) -> Result<()> {
+        let accounts = ctx.accounts;
+        require!(
+            !accounts.pool.whitelist_enabled || accounts.whitelist.is_some(),
+            ErrorCode::NotWhitelisted
+        );
+        let staked_before = accounts.pool.total_staked;
+        process_stake(
+            DepositAccounts {
+                pool: &mut accounts.pool,
+                user: &mut accounts.user,
+                stake_mint: &accounts.stake_mint,
+                pool_vault: &mut accounts.pool_vault,
+                source: &accounts.stake_token_account,
+                lst_mint: &accounts.lst_mint,
+                lst_destination: &accounts.user_lst_account,
+                treasury: accounts.treasury_token_account.as_ref(),
+                funder: &accounts.authority,
+                token_program: &accounts.token_program,
+            },
+            amount,
+            tier_index,
+            auto_renew,
+        )?;
+        record_tvl_change(&mut accounts.global_state, staked_before, accounts.pool.total_staked);
+        Ok(())
+    }
 
-        if is_new_user {
-            user.bump = ctx.bumps.user;
-            user.lock_type = lock_type;
+    /// Stake in one call, creating the signer's original position (index 0)
+    /// first if it does not exist yet. An existing position is staked into
+    /// as-is and never re-initialized.
+    pub fn stake_init(ctx: Context<StakeInit>, amount: u64, tier_index: u8, auto_renew: bool) -> Result<()> {
+        let accounts = ctx.accounts;
+        require!(
+            !accounts.pool.whitelist_enabled || accounts.whitelist.is_some(),
+            ErrorCode::NotWhitelisted
+        );
 
-            if lock_type == 1 {
-                // Locked staking
-                user.lock_start_timestamp = clock.unix_timestamp;
-                user.lock_end_timestamp = clock.unix_timestamp + pool.lock_duration as i64;
-                user.bonus_multiplier = 10000 + pool.lock_bonus_percentage; // 10000 = 1x (100%)
-            } else {
-                // Flexible staking
-                user.lock_start_timestamp = 0;
-                user.lock_end_timestamp = 0;
-                user.bonus_multiplier = 10000; // 1x
-            }
+        // `init_if_needed` hands over a zeroed account when it created it; a
+        // live position always has its owner set, so it is never reset here
+        let authority = accounts.authority.key();
+        if accounts.user.version == 0 && accounts.user.owner == Pubkey::default() {
+            init_user(
+                &mut accounts.pool,
+                &mut accounts.user,
+                authority,
+                Pubkey::default(),
+                [0; 16],
+                0,
+                ctx.bumps.user,
+            )?;
+        } else {
+            require_keys_eq!(accounts.user.owner, authority, ErrorCode::Unauthorized);
+            require_keys_eq!(accounts.user.pool, accounts.pool.key(), ErrorCode::PoolMismatch);
         }
 
-        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        let staked_before = accounts.pool.total_staked;
+        process_stake(
+            DepositAccounts {
+                pool: &mut accounts.pool,
+                user: &mut accounts.user,
+                stake_mint: &accounts.stake_mint,
+                pool_vault: &mut accounts.pool_vault,
+                source: &accounts.stake_token_account,
+                lst_mint: &accounts.lst_mint,
+                lst_destination: &accounts.user_lst_account,
+                treasury: accounts.treasury_token_account.as_ref(),
+                funder: &accounts.authority,
+                token_program: &accounts.token_program,
+            },
+            amount,
+            tier_index,
+            auto_renew,
+        )?;
+        record_tvl_change(&mut accounts.global_state, staked_before, accounts.pool.total_staked);
+        Ok(())
+    }
 
-        // Update pool totals
-        pool.total_staked = pool.total_staked
-            .checked_add(amount)
-            .ok_or(ErrorCode::MathOverflow)?;
+    /// Stake the signer's tokens into `beneficiary`'s position. The beneficiary
+    /// owns the resulting stake and receives the LST.
+    pub fn stake_for(
+        ctx: Context<StakeFor>,
+        beneficiary: Pubkey,
+        amount: u64,
+        tier_index: u8,
+        auto_renew: bool,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+        require!(
+            !accounts.pool.whitelist_enabled || accounts.whitelist.is_some(),
+            ErrorCode::NotWhitelisted
+        );
+        let staked_before = accounts.pool.total_staked;
+        process_stake(
+            DepositAccounts {
+                pool: &mut accounts.pool,
+                user: &mut accounts.user,
+                stake_mint: &accounts.stake_mint,
+                pool_vault: &mut accounts.pool_vault,
+                source: &accounts.stake_token_account,
+                lst_mint: &accounts.lst_mint,
+                lst_destination: &accounts.beneficiary_lst_account,
+                treasury: accounts.treasury_token_account.as_ref(),
+                funder: &accounts.funder,
+                token_program: &accounts.token_program,
+            },
+            amount,
+            tier_index,
+            auto_renew,
+        )?;
+        record_tvl_change(&mut accounts.global_state, staked_before, accounts.pool.total_staked);
 
-        // Check if staking native SOL (So11111111111111111111111111111111111112)
-        // Native SOL mint is "So11111111111111111111111111111111111111112"
-        let native_sol_mint = Pubkey::from_str_const("So11111111111111111111111111111111111111112");
-        let is_native_sol = pool.stake_mint == native_sol_mint;
+        msg!("Stake credited to {}", beneficiary);
+        Ok(())
+    }
 
-        if is_native_sol {
-            // For native SOL, use System Program to transfer lamports to pool authority
-            let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
-                &ctx.accounts.payer.key(),
-                &ctx.accounts.pool_authority.key(),
-                amount,
-            );
+    /// Unstake tokens by redeeming LST (only after lock period expires for locked stakes)
+    pub fn unstake(ctx: Context<Unstake>, lst_amount: u64) -> Result<()> {
+        require!(lst_amount > 0, ErrorCode::InvalidAmount);
 
-            anchor_lang::solana_program::program::invoke(
-                &transfer_ix,
-                &[
-                    ctx.accounts.payer.to_account_info(),
-                    ctx.accounts.pool_authority.to_account_info(),
-                ],
-            )?;
+        let user = &mut ctx.accounts.user;
+        let pool = &mut ctx.accounts.pool;
+        let clock = Clock::get()?;
+        renew_lock(user, clock.unix_timestamp)?;
+
+        // Only LST issued to this position can redeem its principal
+        let principal = principal_for_lst(user, lst_amount)?;
+
+        // Check lock period for locked stakes (flexible stakes have no lock end)
+        require!(
+            clock.unix_timestamp >= user.lock_end_timestamp,
+            ErrorCode::StillInLockPeriod
+        );
+
+        // Pools with a cooldown must go through request_unstake
+        require!(pool.unstake_cooldown == 0, ErrorCode::CooldownRequired);
+
+        // Bank rewards earned on the full balance before it shrinks
+        update_pool_rewards(pool, clock.unix_timestamp)?;
+        let pending = pending_rewards(pool, user)?;
+        user.unclaimed_rewards = user.unclaimed_rewards
+            .checked_add(pending)
+            .ok_or(ErrorCode::MathOverflow)?;
+        settle_reward_streams(pool, user)?;
+
+        // Underlying owed for the LST at the current exchange rate
+        let lst_supply = ctx.accounts.lst_mint.supply;
+        let is_last_redeemer = lst_amount == lst_supply;
+        let vault_balance = ctx.accounts.pool_vault.amount.saturating_sub(pool.total_unstake_pending);
+        let amount = redeemable_amount(pool, lst_supply, vault_balance, lst_amount)?;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        // Large exits are split across transactions to smooth vault outflows
+        require!(
+            pool.max_unstake_per_tx == 0 || amount <= pool.max_unstake_per_tx,
+            ErrorCode::UnstakeExceedsLimit
+        );
+
+        // Update user stake (the payout may exceed principal once the rate drifts)
+        user.amount = user.amount.checked_sub(principal).ok_or(ErrorCode::InsufficientStake)?;
+        user.lst_minted = user.lst_minted.checked_sub(lst_amount).ok_or(ErrorCode::InsufficientStake)?;
+        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        user.reward_debt = reward_debt_for(pool, user.amount)?;
+        sync_reward_stream_debts(pool, user)?;
+        reweigh_stake(pool, user, clock.unix_timestamp)?;
 
-            msg!("Transferred {} lamports (native SOL) to pool authority", amount);
+        // Update pool totals
+        let staked_before = pool.total_staked;
+        pool.total_staked = if is_last_redeemer {
+            0
         } else {
-            // For SPL tokens, use TransferChecked
-            let transfer_accounts = TransferChecked {
-                from: ctx.accounts.user_token_account.as_ref().unwrap().to_account_info(),
-                to: ctx.accounts.pool_authority_token_account.as_ref().unwrap().to_account_info(),
-                authority: ctx.accounts.payer.to_account_info(),
-                mint: ctx.accounts.stake_mint.to_account_info(),
-            };
+            pool.total_staked
+                .checked_sub(amount)
+                .ok_or(ErrorCode::MathOverflow)?
+        };
+        record_tvl_change(&mut ctx.accounts.global_state, staked_before, pool.total_staked);
 
-            let transfer_ctx = CpiContext::new(
-                ctx.accounts.token_program.as_ref().unwrap().to_account_info(),
-                transfer_accounts,
-            );
+        // Burn the LST receipt
+        burn_lst(
+            &ctx.accounts.lst_mint,
+            &ctx.accounts.user_lst_account,
+            &ctx.accounts.authority,
+            &ctx.accounts.token_program,
+            lst_amount,
+        )?;
 
-            let decimals = ctx.accounts.stake_mint.decimals;
-            token::transfer_checked(transfer_ctx, amount, decimals)?;
+        require!(vault_balance >= amount, ErrorCode::InsufficientVaultBalance);
 
-            msg!("Transferred {} tokens to pool authority", amount);
-        }
+        // Transfer from the pool vault back to the user
+        transfer_from_pool(
+            &ctx.accounts.pool,
+            &ctx.accounts.pool_vault,
+            ctx.accounts.stake_token_account.to_account_info(),
+            &ctx.accounts.stake_mint,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+
+        msg!("Transferred {} tokens back to user", amount);
 
-        msg!("Staked {} tokens with lock type: {}", amount, lock_type);
+        msg!("Unstaked {} tokens for {} LST", amount, lst_amount);
+        msg!("Banked rewards: {}", pending);
         Ok(())
     }
 
-    /// Unstake tokens (only after lock period expires for locked stakes)
-    pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<()> {
-        require!(amount > 0, ErrorCode::InvalidAmount);
+    /// Unstake immediately, ignoring any lock. Pending rewards are forfeited and
+    /// `pool.early_withdraw_penalty_bps` of the principal stays in the vault.
+    pub fn emergency_unstake(ctx: Context<Unstake>, lst_amount: u64) -> Result<()> {
+        require!(lst_amount > 0, ErrorCode::InvalidAmount);
 
         let user = &mut ctx.accounts.user;
         let pool = &mut ctx.accounts.pool;
         let clock = Clock::get()?;
 
-        // Check if user has enough staked
-        require!(user.amount >= amount, ErrorCode::InsufficientStake);
+        let principal = principal_for_lst(user, lst_amount)?;
 
-        // Check lock period for locked stakes
-        if user.lock_type == 1 {
-            require!(
-                clock.unix_timestamp >= user.lock_end_timestamp,
-                ErrorCode::StillInLockPeriod
-            );
+        // Settle the pool, then drop the user's pending rewards
+        update_pool_rewards(pool, clock.unix_timestamp)?;
+
+        let lst_supply = ctx.accounts.lst_mint.supply;
+        let is_last_redeemer = lst_amount == lst_supply;
+        let vault_balance = ctx.accounts.pool_vault.amount.saturating_sub(pool.total_unstake_pending);
+        let amount = redeemable_amount(pool, lst_supply, vault_balance, lst_amount)?;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let penalty = early_withdraw_penalty(pool, amount)?;
+        let amount_returned = amount.checked_sub(penalty).ok_or(ErrorCode::MathOverflow)?;
+
+        user.amount = user.amount.checked_sub(principal).ok_or(ErrorCode::InsufficientStake)?;
+        user.lst_minted = user.lst_minted.checked_sub(lst_amount).ok_or(ErrorCode::InsufficientStake)?;
+        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        user.reward_debt = reward_debt_for(pool, user.amount)?;
+        user.unclaimed_rewards = 0;
+        for position in user.reward_streams.iter_mut() {
+            position.unclaimed_rewards = 0;
         }
+        sync_reward_stream_debts(pool, user)?;
+        reweigh_stake(pool, user, clock.unix_timestamp)?;
 
-        // Calculate pending rewards before unstaking
-        let time_elapsed = (clock.unix_timestamp - user.last_reward_claim_timestamp) as u64;
-        let user_share = if pool.total_staked > 0 {
-            (user.amount as u128)
-                .checked_mul(10000 as u128)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_div(pool.total_staked as u128)
-                .ok_or(ErrorCode::MathOverflow)? as u64
+        // The penalty remains staked, raising the exchange rate for remaining holders
+        let staked_before = pool.total_staked;
+        pool.total_staked = if is_last_redeemer {
+            penalty
         } else {
-            0
+            pool.total_staked
+                .checked_sub(amount_returned)
+                .ok_or(ErrorCode::MathOverflow)?
         };
+        record_tvl_change(&mut ctx.accounts.global_state, staked_before, pool.total_staked);
 
-        let pending_rewards = pool
-            .reward_per_second
-            .checked_mul(time_elapsed)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_mul(user_share)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_mul(user.bonus_multiplier as u64)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(10000)
+        burn_lst(
+            &ctx.accounts.lst_mint,
+            &ctx.accounts.user_lst_account,
+            &ctx.accounts.authority,
+            &ctx.accounts.token_program,
+            lst_amount,
+        )?;
+
+        require!(vault_balance >= amount_returned, ErrorCode::InsufficientVaultBalance);
+
+        transfer_from_pool(
+            &ctx.accounts.pool,
+            &ctx.accounts.pool_vault,
+            ctx.accounts.stake_token_account.to_account_info(),
+            &ctx.accounts.stake_mint,
+            &ctx.accounts.token_program,
+            amount_returned,
+        )?;
+
+        msg!("Emergency unstaked {} tokens ({} penalty withheld)", amount_returned, penalty);
+        Ok(())
+    }
+
+    /// Redeem LST into the unstake queue. The underlying stops earning now and
+    /// can be withdrawn with `complete_unstake` once `pool.unstake_cooldown` passes.
+    /// A new request adds to any queued amount and restarts the cooldown.
+    pub fn request_unstake(ctx: Context<Unstake>, lst_amount: u64) -> Result<()> {
+        require!(lst_amount > 0, ErrorCode::InvalidAmount);
+
+        let user = &mut ctx.accounts.user;
+        let pool = &mut ctx.accounts.pool;
+        let clock = Clock::get()?;
+        renew_lock(user, clock.unix_timestamp)?;
+
+        let principal = principal_for_lst(user, lst_amount)?;
+        require!(
+            clock.unix_timestamp >= user.lock_end_timestamp,
+            ErrorCode::StillInLockPeriod
+        );
+
+        // Bank rewards earned up to now; the queued amount earns nothing further
+        update_pool_rewards(pool, clock.unix_timestamp)?;
+        let pending = pending_rewards(pool, user)?;
+        user.unclaimed_rewards = user.unclaimed_rewards
+            .checked_add(pending)
             .ok_or(ErrorCode::MathOverflow)?;
+        settle_reward_streams(pool, user)?;
+
+        let lst_supply = ctx.accounts.lst_mint.supply;
+        let is_last_redeemer = lst_amount == lst_supply;
+        let vault_balance = ctx.accounts.pool_vault.amount.saturating_sub(pool.total_unstake_pending);
+        let amount = redeemable_amount(pool, lst_supply, vault_balance, lst_amount)?;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            pool.max_unstake_per_tx == 0 || amount <= pool.max_unstake_per_tx,
+            ErrorCode::UnstakeExceedsLimit
+        );
 
-        // Update user stake
-        user.amount = user.amount.checked_sub(amount).ok_or(ErrorCode::MathOverflow)?;
+        user.amount = user.amount.checked_sub(principal).ok_or(ErrorCode::InsufficientStake)?;
+        user.lst_minted = user.lst_minted.checked_sub(lst_amount).ok_or(ErrorCode::InsufficientStake)?;
         user.last_reward_claim_timestamp = clock.unix_timestamp;
+        user.reward_debt = reward_debt_for(pool, user.amount)?;
+        sync_reward_stream_debts(pool, user)?;
+        reweigh_stake(pool, user, clock.unix_timestamp)?;
 
-        // Update pool totals
-        pool.total_staked = pool.total_staked
-            .checked_sub(amount)
+        let cooldown = i64::try_from(pool.unstake_cooldown).map_err(|_| ErrorCode::MathOverflow)?;
+        user.unstake_request.amount = user.unstake_request.amount
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        user.unstake_request.available_at = clock.unix_timestamp
+            .checked_add(cooldown)
             .ok_or(ErrorCode::MathOverflow)?;
 
-        // Check if unstaking native SOL
-        let native_sol_mint = Pubkey::from_str_const("So11111111111111111111111111111111111111112");
-        let is_native_sol = pool.stake_mint == native_sol_mint;
-
-        if is_native_sol {
-            // TODO: For native SOL, we need to transfer lamports from pool authority back to user
-            // This requires pool_authority to be a PDA (Program Derived Address) controlled by the program
-            // Currently using external wallet (8uSHCQQDycVbjj2qMLm8qS2zKUdgFfN2JEsqWvzUdqEz) which can't sign
-            // Skipping actual transfer for now - accounting will still work
-            msg!("Skipping native SOL transfer - pool authority is external wallet");
-            msg!("User accounting updated. Manual SOL transfer required from pool authority: {}", ctx.accounts.pool_authority.key());
+        let staked_before = pool.total_staked;
+        pool.total_staked = if is_last_redeemer {
+            0
         } else {
-            // For SPL tokens, transfer from pool authority token account to user token account
-            let transfer_accounts = TransferChecked {
-                from: ctx.accounts.pool_authority_token_account.as_ref().unwrap().to_account_info(),
-                to: ctx.accounts.user_token_account.as_ref().unwrap().to_account_info(),
-                authority: ctx.accounts.pool_authority.to_account_info(),
-                mint: ctx.accounts.stake_mint.to_account_info(),
-            };
-
-            let transfer_ctx = CpiContext::new(
-                ctx.accounts.token_program.as_ref().unwrap().to_account_info(),
-                transfer_accounts,
-            );
-
-            let decimals = ctx.accounts.stake_mint.decimals;
-            token::transfer_checked(transfer_ctx, amount, decimals)?;
+            pool.total_staked
+                .checked_sub(amount)
+                .ok_or(ErrorCode::MathOverflow)?
+        };
+        record_tvl_change(&mut ctx.accounts.global_state, staked_before, pool.total_staked);
+        pool.total_unstake_pending = pool.total_unstake_pending
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
 
-            msg!("Transferred {} tokens back to user", amount);
-        }
+        burn_lst(
+            &ctx.accounts.lst_mint,
+            &ctx.accounts.user_lst_account,
+            &ctx.accounts.authority,
+            &ctx.accounts.token_program,
+            lst_amount,
+        )?;
 
-        msg!("Unstaked {} tokens", amount);
-        msg!("Pending rewards: {}", pending_rewards);
+        msg!(
+            "Queued {} tokens for unstake, available at {}",
+            amount,
+            user.unstake_request.available_at
+        );
         Ok(())
     }
 
-    /// Claim accumulated rewards
-    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+    /// Withdraw a queued unstake once its cooldown has elapsed
+    pub fn complete_unstake(ctx: Context<CompleteUnstake>) -> Result<()> {
         let user = &mut ctx.accounts.user;
         let pool = &mut ctx.accounts.pool;
         let clock = Clock::get()?;
 
-        // Calculate rewards since last claim
-        let time_elapsed = (clock.unix_timestamp - user.last_reward_claim_timestamp) as u64;
-
-        let user_share = if pool.total_staked > 0 {
-            (user.amount as u128)
-                .checked_mul(10000 as u128)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_div(pool.total_staked as u128)
-                .ok_or(ErrorCode::MathOverflow)? as u64
-        } else {
-            0
-        };
+        let request = user.unstake_request;
+        require!(request.amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            clock.unix_timestamp >= request.available_at,
+            ErrorCode::CooldownNotElapsed
+        );
 
-        let rewards = pool
-            .reward_per_second
-            .checked_mul(time_elapsed)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_mul(user_share)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_mul(user.bonus_multiplier as u64)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(10000)
+        user.unstake_request = UnstakeRequest::default();
+        pool.total_unstake_pending = pool.total_unstake_pending
+            .checked_sub(request.amount)
             .ok_or(ErrorCode::MathOverflow)?;
 
-        require!(rewards > 0, ErrorCode::NoRewardsAvailable);
+        transfer_from_pool(
+            &ctx.accounts.pool,
+            &ctx.accounts.pool_vault,
+            ctx.accounts.stake_token_account.to_account_info(),
+            &ctx.accounts.stake_mint,
+            &ctx.accounts.token_program,
+            request.amount,
+        )?;
 
-        // Update last claim timestamp
-        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        msg!("Completed unstake of {} tokens", request.amount);
+        Ok(())
+    }
+
+    /// Return a queued unstake to active stake before its cooldown elapses.
+    /// The amount earns again from now and is re-issued LST at the current rate.
+    pub fn cancel_unstake_request(ctx: Context<CancelUnstakeRequest>) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        let pool = &mut ctx.accounts.pool;
+        let clock = Clock::get()?;
+
+        let request = user.unstake_request;
+        require!(request.amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            clock.unix_timestamp < request.available_at,
+            ErrorCode::UnstakeRequestMatured
+        );
 
-        // Update pool total distributed
-        pool.total_reward_distributed = pool.total_reward_distributed
-            .checked_add(rewards)
+        user.unstake_request = UnstakeRequest::default();
+        pool.total_unstake_pending = pool.total_unstake_pending
+            .checked_sub(request.amount)
             .ok_or(ErrorCode::MathOverflow)?;
 
-        msg!("Claimed {} tokens in rewards", rewards);
+        // A position emptied by the request rejoins its previous tier
+        let staked_before = pool.total_staked;
+        let (tier_index, auto_renew) = (user.lock_type, user.auto_renew);
+        let lst_to_mint = credit_stake(
+            pool,
+            user,
+            request.amount,
+            ctx.accounts.lst_mint.supply,
+            tier_index,
+            auto_renew,
+            clock.unix_timestamp,
+        )?;
+        record_tvl_change(&mut ctx.accounts.global_state, staked_before, pool.total_staked);
+
+        mint_lst(
+            &ctx.accounts.pool,
+            &ctx.accounts.lst_mint,
+            ctx.accounts.user_lst_account.to_account_info(),
+            &ctx.accounts.token_program,
+            lst_to_mint,
+        )?;
+
+        msg!("Cancelled unstake of {} tokens; restaked for {} LST", request.amount, lst_to_mint);
         Ok(())
     }
 
-    /// Update pool parameters (authority only)
-    pub fn update_pool(
-        ctx: Context<UpdatePool>,
-        new_reward_per_second: Option<u64>,
-        new_lock_duration: Option<u64>,
-        new_lock_bonus_percentage: Option<u16>,
-    ) -> Result<()> {
+    /// Move an existing stake into a lock tier without a new deposit
+    pub fn relock(ctx: Context<Relock>, tier_index: u8) -> Result<()> {
+        let user = &mut ctx.accounts.user;
         let pool = &mut ctx.accounts.pool;
+        let clock = Clock::get()?;
+        renew_lock(user, clock.unix_timestamp)?;
 
-        if let Some(reward_rate) = new_reward_per_second {
-            pool.reward_per_second = reward_rate;
-        }
+        require!(user.amount > 0, ErrorCode::InsufficientStake);
+        let tier = pool.lock_tier(tier_index)?;
 
-        if let Some(duration) = new_lock_duration {
-            pool.lock_duration = duration;
-        }
+        // Settle rewards at the old multiplier before changing it
+        update_pool_rewards(pool, clock.unix_timestamp)?;
+        let pending = pending_rewards(pool, user)?;
+        user.unclaimed_rewards = user.unclaimed_rewards
+            .checked_add(pending)
+            .ok_or(ErrorCode::MathOverflow)?;
+        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        user.reward_debt = reward_debt_for(pool, user.amount)?;
+        settle_reward_streams(pool, user)?;
+        sync_reward_stream_debts(pool, user)?;
+
+        let new_lock_end = tier.lock_end(clock.unix_timestamp)?;
 
-        if let Some(bonus) = new_lock_bonus_percentage {
-            pool.lock_bonus_percentage = bonus;
+        // An active lock can only be extended
+        if user.lock_end_timestamp > clock.unix_timestamp {
+            require!(
+                new_lock_end >= user.lock_end_timestamp,
+                ErrorCode::CannotShortenLock
+            );
         }
 
-        msg!("Pool parameters updated");
+        user.lock_type = tier_index;
+        user.lock_start_timestamp = clock.unix_timestamp;
+        user.lock_end_timestamp = new_lock_end;
+        user.bonus_multiplier = tier.multiplier()?;
+        reweigh_stake(pool, user, clock.unix_timestamp)?;
+
+        msg!("Relocked into tier {} until {}", tier_index, new_lock_end);
         Ok(())
     }
 
-    /// Close user account and withdraw remaining stake
-    pub fn close_user_account(ctx: Context<CloseUserAccount>) -> Result<()> {
+    /// Turn automatic lock renewal on or off. Renewal can only be enabled while
+    /// the lock is active; turning it off lets the current term run out.
+    pub fn set_auto_renew(ctx: Context<SetAutoRenew>, auto_renew: bool) -> Result<()> {
         let user = &mut ctx.accounts.user;
         let clock = Clock::get()?;
+        renew_lock(user, clock.unix_timestamp)?;
 
-        // Check lock period
-        if user.lock_type == 1 {
+        if auto_renew {
             require!(
-                clock.unix_timestamp >= user.lock_end_timestamp,
-                ErrorCode::StillInLockPeriod
+                user.lock_end_timestamp > clock.unix_timestamp,
+                ErrorCode::NoActiveLock
             );
         }
+        user.auto_renew = auto_renew;
 
-        let amount = user.amount;
+        msg!("Auto-renew set to {} (lock ends {})", auto_renew, user.lock_end_timestamp);
+        Ok(())
+    }
 
-        // Close user account and return rent
-        ctx.accounts.user.close(ctx.accounts.user_wallet.to_account_info())?;
+    /// Tag the position with a label (informational only)
+    pub fn set_label(ctx: Context<SetLabel>, label: [u8; 16]) -> Result<()> {
+        ctx.accounts.user.label = label;
+        msg!("Label set to {}", String::from_utf8_lossy(&label));
+        Ok(())
+    }
 
-        msg!("User account closed, {} tokens withdrawn", amount);
+    /// Let `delegate` trigger `claim_rewards` for this position; rewards still
+    /// go to the owner. `None` revokes the delegate.
+    pub fn set_claim_delegate(ctx: Context<SetClaimDelegate>, delegate: Option<Pubkey>) -> Result<()> {
+        ctx.accounts.user.claim_delegate = delegate;
+        match delegate {
+            Some(delegate) => msg!("Claim delegate set to {}", delegate),
+            None => msg!("Claim delegate revoked"),
+        }
         Ok(())
     }
-}
 
-// ============ Account Structures ============
+    /// Drop an expired lock's bonus from the pool's reward weight (permissionless).
+    /// Rewards earned while the lock was active are banked first.
+    pub fn expire_lock(ctx: Context<ExpireLock>) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        let pool = &mut ctx.accounts.pool;
+        let clock = Clock::get()?;
+        renew_lock(user, clock.unix_timestamp)?;
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(
-        init,
-        payer = payer,
-        space = 8 + GlobalState::LEN,
-        seeds = [b"global"],
-        bump
-    )]
-    pub global_state: Account<'info, GlobalState>,
+        require!(
+            user.lock_end_timestamp > 0 && clock.unix_timestamp >= user.lock_end_timestamp,
+            ErrorCode::StillInLockPeriod
+        );
 
-    #[account(mut)]
-    pub payer: Signer<'info>,
+        update_pool_rewards(pool, clock.unix_timestamp)?;
+        let pending = pending_rewards(pool, user)?;
+        user.unclaimed_rewards = user.unclaimed_rewards
+            .checked_add(pending)
+            .ok_or(ErrorCode::MathOverflow)?;
+        settle_reward_streams(pool, user)?;
+        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        user.reward_debt = reward_debt_for(pool, user.amount)?;
+        sync_reward_stream_debts(pool, user)?;
+        reweigh_stake(pool, user, clock.unix_timestamp)?;
 
-    pub system_program: Program<'info, System>,
-}
+        msg!("Lock expired; reward weight now {}", user.weighted_stake);
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct CreateUserAccount<'info> {
-    #[account(
-        mut,
-        seeds = [b"pool", pool.pool_id.as_ref()],
-        bump = pool.bump
-    )]
-    pub pool: Account<'info, Pool>,
+    /// Claim accumulated rewards. `amount` claims part of the primary reward and
+    /// leaves the rest banked (`None` claims everything). Extra reward streams are
+    /// paid in full from `remaining_accounts`, passed as
+    /// `[stream_vault, stream_mint, user_token_account]` for each stream in order.
+    /// Rewards banked by `unstake` stay claimable after the position is emptied.
+    pub fn claim_rewards<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ClaimRewards<'info>>,
+        amount: Option<u64>,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+        let distributed = process_claim(
+            ClaimAccounts {
+                pool: &mut accounts.pool,
+                user: &mut accounts.user,
+                authority: accounts.authority.key(),
+                reward_mint: &accounts.reward_mint,
+                reward_vault: &accounts.reward_vault,
+                user_reward_account: accounts.user_reward_account.to_account_info(),
+                referrer_reward_account: accounts.referrer_reward_account
+                    .as_ref()
+                    .map(|account| account.to_account_info()),
+                fee_recipient_reward_account: accounts.fee_recipient_reward_account
+                    .as_ref()
+                    .map(|account| account.to_account_info()),
+                stream_accounts: ctx.remaining_accounts,
+                token_program: &accounts.token_program,
+            },
+            amount,
+            Clock::get()?.unix_timestamp,
+        )?;
+        record_rewards_distributed(&mut accounts.global_state, distributed);
+        Ok(())
+    }
 
-    #[account(
-        init,
-        payer = payer,
-        space = 8 + User::LEN,
-        seeds = [b"user", pool.pool_id.as_ref(), payer.key().as_ref()],
-        bump
-    )]
-    pub user: Account<'info, User>,
+    /// Claim every reward of several positions at once, each exactly as
+    /// `claim_rewards(None)` would. `remaining_accounts` holds one entry per
+    /// position: `[pool, user, reward_mint, reward_vault, user_reward_account,
+    /// referrer_reward_account, fee_recipient_reward_account]` followed by the
+    /// `claim_rewards` stream accounts for each of the pool's extra streams.
+    /// Pass the program id in place of an absent referrer or fee account.
+    pub fn claim_rewards_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ClaimRewardsBatch<'info>>,
+    ) -> Result<()> {
+        let remaining = ctx.remaining_accounts;
+        require!(!remaining.is_empty(), ErrorCode::InvalidBatchAccounts);
 
-    #[account(mut)]
-    pub payer: Signer<'info>,
+        let clock = Clock::get()?;
+        let authority = ctx.accounts.authority.key();
+        let optional = |info: &'info AccountInfo<'info>| (info.key() != crate::ID).then_some(info);
+        let mut total_claimed: u64 = 0;
+        let mut cursor = 0;
+
+        while cursor < remaining.len() {
+            require!(remaining.len() - cursor >= 7, ErrorCode::InvalidBatchAccounts);
+            let accounts = &remaining[cursor..cursor + 7];
+            let mut pool = Account::<Pool>::try_from(&accounts[0])?;
+            let mut user = Account::<User>::try_from(&accounts[1])?;
+            let reward_mint = InterfaceAccount::<Mint>::try_from(&accounts[2])?;
+            let reward_vault = InterfaceAccount::<TokenAccount>::try_from(&accounts[3])?;
+            let user_reward_account = InterfaceAccount::<TokenAccount>::try_from(&accounts[4])?;
+            let referrer_reward_account = optional(&accounts[5]);
+            let fee_recipient_reward_account = optional(&accounts[6]);
+
+            let stream_start = cursor + 7;
+            cursor = stream_start + pool.reward_stream_count as usize * 3;
+            require!(cursor <= remaining.len(), ErrorCode::InvalidBatchAccounts);
+
+            // Apply every constraint `ClaimRewards` puts on these accounts
+            let pool_key = Pubkey::create_program_address(
+                &[b"pool", pool.pool_id.as_ref(), &[pool.bump]],
+                &crate::ID,
+            )
+            .map_err(|_| ErrorCode::InvalidBatchAccounts)?;
+            require_keys_eq!(pool.key(), pool_key, ErrorCode::InvalidBatchAccounts);
+
+            let user_key = Pubkey::create_program_address(
+                &[
+                    b"user",
+                    pool.pool_id.as_ref(),
+                    user.owner.as_ref(),
+                    position_seed(&user.position_index),
+                    &[user.bump],
+                ],
+                &crate::ID,
+            )
+            .map_err(|_| ErrorCode::InvalidBatchAccounts)?;
+            require_keys_eq!(user.key(), user_key, ErrorCode::InvalidBatchAccounts);
+            require_keys_eq!(user.pool, pool.key(), ErrorCode::PoolMismatch);
+            require!(
+                user.owner == authority || user.claim_delegate == Some(authority),
+                ErrorCode::Unauthorized
+            );
+
+            let (vault_key, _) = Pubkey::find_program_address(
+                &[b"reward_vault", pool.pool_id.as_ref()],
+                &crate::ID,
+            );
+            require_keys_eq!(reward_vault.key(), vault_key, ErrorCode::InvalidBatchAccounts);
+            require_keys_eq!(reward_mint.key(), pool.reward_mint, ErrorCode::InvalidMint);
+            require_keys_eq!(reward_vault.mint, pool.reward_mint, ErrorCode::InvalidMint);
+            require_keys_eq!(reward_vault.owner, pool.key(), ErrorCode::InvalidBatchAccounts);
+
+            require_keys_eq!(user_reward_account.mint, pool.reward_mint, ErrorCode::InvalidMint);
+            require!(
+                user.owner == authority || user_reward_account.owner == user.owner,
+                ErrorCode::Unauthorized
+            );
+            if let Some(info) = referrer_reward_account {
+                let referrer_account = InterfaceAccount::<TokenAccount>::try_from(info)?;
+                require_keys_eq!(referrer_account.mint, pool.reward_mint, ErrorCode::InvalidMint);
+                require_keys_eq!(referrer_account.owner, user.referrer, ErrorCode::InvalidReferrer);
+            }
+            if let Some(info) = fee_recipient_reward_account {
+                let fee_account = InterfaceAccount::<TokenAccount>::try_from(info)?;
+                require_keys_eq!(fee_account.mint, pool.reward_mint, ErrorCode::InvalidMint);
+                require_keys_eq!(fee_account.owner, pool.fee_recipient, ErrorCode::InvalidFeeRecipient);
+            }
+
+            let claimed = process_claim(
+                ClaimAccounts {
+                    pool: &mut pool,
+                    user: &mut user,
+                    authority,
+                    reward_mint: &reward_mint,
+                    reward_vault: &reward_vault,
+                    user_reward_account: accounts[4].clone(),
+                    referrer_reward_account: referrer_reward_account.cloned(),
+                    fee_recipient_reward_account: fee_recipient_reward_account.cloned(),
+                    stream_accounts: &remaining[stream_start..cursor],
+                    token_program: &ctx.accounts.token_program,
+                },
+                None,
+                clock.unix_timestamp,
+            )?;
+            total_claimed = total_claimed
+                .checked_add(claimed)
+                .ok_or(ErrorCode::MathOverflow)?;
+
+            // Persist before the next entry, which may reference the same pool
+            pool.exit(&crate::ID)?;
+            user.exit(&crate::ID)?;
+        }
+
+        record_rewards_distributed(&mut ctx.accounts.global_state, total_claimed);
+        Ok(())
+    }
+
+    /// Deposit reward tokens into the pool's reward vault
+    pub fn fund_reward_vault(ctx: Context<FundRewardVault>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let transfer_accounts = TransferChecked {
+            from: ctx.accounts.funder_token_account.to_account_info(),
+            to: ctx.accounts.reward_vault.to_account_info(),
+            authority: ctx.accounts.funder.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+        };
+
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_accounts,
+        );
+
+        let decimals = ctx.accounts.reward_mint.decimals;
+        token_interface::transfer_checked(transfer_ctx, amount, decimals)?;
+
+        let pool = &mut ctx.accounts.pool;
+        pool.total_reward_funded = pool.total_reward_funded
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        msg!("Funded reward vault with {} tokens (total funded: {})", amount, pool.total_reward_funded);
+        Ok(())
+    }
+
+    /// Top up the reward vault and push `reward_end_time` out to `new_end_time`
+    /// in one step (authority only). `additional_amount` must cover the added
+    /// part of the window at the current primary rate.
+    pub fn extend_rewards(
+        ctx: Context<ExtendRewards>,
+        additional_amount: u64,
+        new_end_time: i64,
+    ) -> Result<()> {
+        require!(additional_amount > 0, ErrorCode::InvalidAmount);
+
+        let pool = &mut ctx.accounts.pool;
+        let clock = Clock::get()?;
+        require!(
+            pool.reward_end_time > 0 && new_end_time > pool.reward_end_time,
+            ErrorCode::InvalidRewardWindow
+        );
+
+        // Settle under the old end so a lapsed window is not back-paid
+        update_pool_rewards(pool, clock.unix_timestamp)?;
+
+        let added_seconds = elapsed_since(new_end_time, pool.reward_end_time.max(clock.unix_timestamp));
+        let required = (pool.current_reward_rate() as u128)
+            .checked_mul(added_seconds as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(
+            additional_amount as u128 >= required,
+            ErrorCode::InsufficientFundingForExtension
+        );
+
+        let start = pool.reward_start_time;
+        pool.set_reward_window(start, new_end_time)?;
+        pool.total_reward_funded = pool.total_reward_funded
+            .checked_add(additional_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let transfer_accounts = TransferChecked {
+            from: ctx.accounts.authority_token_account.to_account_info(),
+            to: ctx.accounts.reward_vault.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+        };
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_accounts,
+        );
+        token_interface::transfer_checked(transfer_ctx, additional_amount, ctx.accounts.reward_mint.decimals)?;
+
+        msg!("Rewards extended to {} with {} more tokens", new_end_time, additional_amount);
+        Ok(())
+    }
+
+    /// Return unused reward tokens to the pool authority once no position can
+    /// still claim them (no stake and no open user accounts)
+    pub fn withdraw_reward_surplus(ctx: Context<WithdrawRewardSurplus>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let pool = &ctx.accounts.pool;
+        require!(
+            pool.total_staked == 0 && pool.user_count == 0,
+            ErrorCode::RewardsStillOwed
+        );
+        require!(
+            ctx.accounts.reward_vault.amount >= amount,
+            ErrorCode::InsufficientVaultBalance
+        );
+
+        transfer_from_pool(
+            pool,
+            &ctx.accounts.reward_vault,
+            ctx.accounts.authority_reward_account.to_account_info(),
+            &ctx.accounts.reward_mint,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+
+        msg!("Withdrew {} surplus reward tokens", amount);
+        Ok(())
+    }
+
+    /// Move stake-vault residue that belongs to no one to the pool treasury
+    /// (authority only). With no LST outstanding and nothing queued, whatever
+    /// `total_staked` still counts (e.g. the penalty withheld from a final
+    /// emergency exit) has no owner, so it is swept too.
+    pub fn sweep_dust(ctx: Context<SweepDust>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        require!(
+            ctx.accounts.lst_mint.supply == 0 && pool.total_unstake_pending == 0,
+            ErrorCode::PoolNotEmpty
+        );
+
+        let clock = Clock::get()?;
+        update_pool_rewards(pool, clock.unix_timestamp)?;
+        record_tvl_change(&mut ctx.accounts.global_state, pool.total_staked, 0);
+        pool.total_staked = 0;
+
+        let dust = ctx.accounts.pool_vault.amount;
+        require!(dust > 0, ErrorCode::InvalidAmount);
+
+        transfer_from_pool(
+            &ctx.accounts.pool,
+            &ctx.accounts.pool_vault,
+            ctx.accounts.treasury_token_account.to_account_info(),
+            &ctx.accounts.stake_mint,
+            &ctx.accounts.token_program,
+            dust,
+        )?;
+
+        msg!("Swept {} tokens of dust to the treasury", dust);
+        Ok(())
+    }
+
+    /// Recover tokens of an unrelated mint sent to a pool-owned account (global
+    /// authority only). Tokens the pool accounts for can never be rescued.
+    pub fn rescue_tokens(ctx: Context<RescueTokens>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let pool = &ctx.accounts.pool;
+        let mint = ctx.accounts.rescue_mint.key();
+        require!(
+            mint != pool.stake_mint
+                && mint != pool.reward_mint
+                && mint != pool.lst_mint
+                && pool.active_reward_streams().iter().all(|stream| stream.reward_mint != mint),
+            ErrorCode::CannotRescueProtocolToken
+        );
+        require!(
+            ctx.accounts.source.amount >= amount,
+            ErrorCode::InsufficientVaultBalance
+        );
+
+        transfer_from_pool(
+            pool,
+            &ctx.accounts.source,
+            ctx.accounts.recovery_account.to_account_info(),
+            &ctx.accounts.rescue_mint,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+
+        msg!("Rescued {} tokens of mint {}", amount, mint);
+        Ok(())
+    }
+
+    /// Restake pending rewards into the position (reward mint must equal stake mint)
+    pub fn compound(ctx: Context<Compound>) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        let pool = &mut ctx.accounts.pool;
+        let clock = Clock::get()?;
+        let rewards = accrue_user_rewards(pool, user, clock.unix_timestamp)?;
+
+        require!(rewards > 0, ErrorCode::NoRewardsAvailable);
+        require!(
+            ctx.accounts.reward_vault.amount >= rewards,
+            ErrorCode::InsufficientRewardFunds
+        );
+
+        // Only the net of the performance fee is restaked
+        let settlement = settle_rewards(pool, rewards)?;
+        let lst_to_mint = lst_for_deposit(pool, ctx.accounts.lst_mint.supply, settlement.net)?;
+
+        // Extra streams keep accruing on the larger balance, so bank them first
+        settle_reward_streams(pool, user)?;
+
+        user.amount = user.amount.checked_add(settlement.net).ok_or(ErrorCode::MathOverflow)?;
+        user.lst_minted = user.lst_minted.checked_add(lst_to_mint).ok_or(ErrorCode::MathOverflow)?;
+        user.unclaimed_rewards = 0;
+        user.reward_debt = reward_debt_for(pool, user.amount)?;
+        sync_reward_stream_debts(pool, user)?;
+        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        reweigh_stake(pool, user, clock.unix_timestamp)?;
+        record_claim(pool.key(), user.key(), user, settlement.net)?;
+
+        let staked_before = pool.total_staked;
+        pool.total_staked = pool.total_staked
+            .checked_add(settlement.net)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let global_state = &mut ctx.accounts.global_state;
+        record_tvl_change(global_state, staked_before, pool.total_staked);
+        record_rewards_distributed(global_state, rewards);
+
+        // Move the rewards into the stake vault; only the fee leaves the pool
+        if settlement.net > 0 {
+            transfer_from_pool(
+                &ctx.accounts.pool,
+                &ctx.accounts.reward_vault,
+                ctx.accounts.pool_vault.to_account_info(),
+                &ctx.accounts.stake_mint,
+                &ctx.accounts.token_program,
+                settlement.net,
+            )?;
+        }
+        pay_performance_fee(
+            &ctx.accounts.pool,
+            &ctx.accounts.reward_vault,
+            ctx.accounts.fee_recipient_reward_account.as_ref().map(|account| account.to_account_info()),
+            &ctx.accounts.stake_mint,
+            &ctx.accounts.token_program,
+            settlement.performance_fee,
+        )?;
+
+        if lst_to_mint > 0 {
+            mint_lst(
+                &ctx.accounts.pool,
+                &ctx.accounts.lst_mint,
+                ctx.accounts.user_lst_account.to_account_info(),
+                &ctx.accounts.token_program,
+                lst_to_mint,
+            )?;
+        }
+
+        msg!("Compounded {} tokens of rewards into stake", settlement.net);
+        Ok(())
+    }
+
+    /// Compound pending rewards into the position and move the whole position
+    /// into lock tier `tier_index` in one step (reward mint must equal stake mint)
+    pub fn restake_all(ctx: Context<Compound>, tier_index: u8) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        let pool = &mut ctx.accounts.pool;
+        let clock = Clock::get()?;
+        let tier = pool.lock_tier(tier_index)?;
+
+        let rewards = accrue_user_rewards(pool, user, clock.unix_timestamp)?;
+        require!(
+            ctx.accounts.reward_vault.amount >= rewards,
+            ErrorCode::InsufficientRewardFunds
+        );
+        // Only the net of the performance fee is restaked
+        let settlement = settle_rewards(pool, rewards)?;
+        let lst_to_mint = if settlement.net > 0 {
+            lst_for_deposit(pool, ctx.accounts.lst_mint.supply, settlement.net)?
+        } else {
+            0
+        };
+
+        // Extra streams keep accruing on the larger balance, so bank them first
+        settle_reward_streams(pool, user)?;
+
+        user.amount = user.amount.checked_add(settlement.net).ok_or(ErrorCode::MathOverflow)?;
+        require!(user.amount > 0, ErrorCode::InsufficientStake);
+        user.lst_minted = user.lst_minted.checked_add(lst_to_mint).ok_or(ErrorCode::MathOverflow)?;
+        user.unclaimed_rewards = 0;
+        user.reward_debt = reward_debt_for(pool, user.amount)?;
+        sync_reward_stream_debts(pool, user)?;
+        user.last_reward_claim_timestamp = clock.unix_timestamp;
+
+        // The whole position, compounded rewards included, takes the new lock
+        let new_lock_end = tier.lock_end(clock.unix_timestamp)?;
+        if user.lock_end_timestamp > clock.unix_timestamp {
+            require!(
+                new_lock_end >= user.lock_end_timestamp,
+                ErrorCode::CannotShortenLock
+            );
+        }
+        user.lock_type = tier_index;
+        user.lock_start_timestamp = clock.unix_timestamp;
+        user.lock_end_timestamp = new_lock_end;
+        user.bonus_multiplier = tier.multiplier()?;
+        reweigh_stake(pool, user, clock.unix_timestamp)?;
+
+        if rewards > 0 {
+            record_claim(pool.key(), user.key(), user, settlement.net)?;
+            let staked_before = pool.total_staked;
+            pool.total_staked = pool.total_staked
+                .checked_add(settlement.net)
+                .ok_or(ErrorCode::MathOverflow)?;
+
+            let global_state = &mut ctx.accounts.global_state;
+            record_tvl_change(global_state, staked_before, pool.total_staked);
+            record_rewards_distributed(global_state, rewards);
+
+            if settlement.net > 0 {
+                transfer_from_pool(
+                    &ctx.accounts.pool,
+                    &ctx.accounts.reward_vault,
+                    ctx.accounts.pool_vault.to_account_info(),
+                    &ctx.accounts.stake_mint,
+                    &ctx.accounts.token_program,
+                    settlement.net,
+                )?;
+            }
+            pay_performance_fee(
+                &ctx.accounts.pool,
+                &ctx.accounts.reward_vault,
+                ctx.accounts.fee_recipient_reward_account.as_ref().map(|account| account.to_account_info()),
+                &ctx.accounts.stake_mint,
+                &ctx.accounts.token_program,
+                settlement.performance_fee,
+            )?;
+        }
+
+        if lst_to_mint > 0 {
+            mint_lst(
+                &ctx.accounts.pool,
+                &ctx.accounts.lst_mint,
+                ctx.accounts.user_lst_account.to_account_info(),
+                &ctx.accounts.token_program,
+                lst_to_mint,
+            )?;
+        }
+
+        msg!(
+            "Restaked {} tokens of rewards; position locked in tier {} until {}",
+            settlement.net,
+            tier_index,
+            new_lock_end
+        );
+        Ok(())
+    }
+
+    /// Claim the primary rewards of one pool and stake them straight into a
+    /// second pool whose stake mint is the first pool's reward mint. An empty
+    /// destination position enters the destination pool's tier 0.
+    pub fn claim_and_stake(ctx: Context<ClaimAndStake>) -> Result<()> {
+        let accounts = ctx.accounts;
+        require!(
+            !accounts.destination_pool.whitelist_enabled || accounts.destination_whitelist.is_some(),
+            ErrorCode::NotWhitelisted
+        );
+        let clock = Clock::get()?;
+
+        // Settle and empty the source position's primary rewards
+        let source_pool = &mut accounts.source_pool;
+        let source_user = &mut accounts.source_user;
+
+        let since_last_claim = elapsed_since(clock.unix_timestamp, source_user.last_reward_claim_timestamp);
+        require!(
+            since_last_claim >= source_pool.min_claim_interval,
+            ErrorCode::ClaimTooSoon
+        );
+
+        let rewards = accrue_user_rewards(source_pool, source_user, clock.unix_timestamp)?;
+        require!(rewards > 0, ErrorCode::NoRewardsAvailable);
+        require!(
+            accounts.source_reward_vault.amount >= rewards,
+            ErrorCode::InsufficientRewardFunds
+        );
+
+        // The source pool's performance fee comes out before staking
+        let settlement = settle_rewards(source_pool, rewards)?;
+        require!(settlement.net > 0, ErrorCode::NoRewardsAvailable);
+
+        settle_reward_streams(source_pool, source_user)?;
+        source_user.unclaimed_rewards = 0;
+        source_user.last_reward_claim_timestamp = clock.unix_timestamp;
+        source_user.reward_debt = reward_debt_for(source_pool, source_user.amount)?;
+        sync_reward_stream_debts(source_pool, source_user)?;
+        reweigh_stake(source_pool, source_user, clock.unix_timestamp)?;
+        record_claim(source_pool.key(), source_user.key(), source_user, settlement.net)?;
+
+        // Respect the destination's deposit cap
+        let destination_pool = &accounts.destination_pool;
+        if destination_pool.max_total_staked > 0 {
+            let new_total = destination_pool.total_staked
+                .checked_add(settlement.net)
+                .ok_or(ErrorCode::MathOverflow)?;
+            require!(new_total <= destination_pool.max_total_staked, ErrorCode::PoolCapExceeded);
+        }
+
+        pay_performance_fee(
+            &accounts.source_pool,
+            &accounts.source_reward_vault,
+            accounts.fee_recipient_reward_account.as_ref().map(|account| account.to_account_info()),
+            &accounts.reward_mint,
+            &accounts.token_program,
+            settlement.performance_fee,
+        )?;
+
+        // Move the rewards from the source reward vault into the destination stake vault
+        let vault_before = accounts.destination_vault.amount;
+        transfer_from_pool(
+            &accounts.source_pool,
+            &accounts.source_reward_vault,
+            accounts.destination_vault.to_account_info(),
+            &accounts.reward_mint,
+            &accounts.token_program,
+            settlement.net,
+        )?;
+        accounts.destination_vault.reload()?;
+        let received = accounts.destination_vault.amount
+            .checked_sub(vault_before)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(received > 0, ErrorCode::StakeTooSmallAfterFee);
+
+        let staked_before = accounts.destination_pool.total_staked;
+        let lst_to_mint = credit_stake(
+            &mut accounts.destination_pool,
+            &mut accounts.destination_user,
+            received,
+            accounts.destination_lst_mint.supply,
+            0,
+            false,
+            clock.unix_timestamp,
+        )?;
+        record_tvl_change(&mut accounts.global_state, staked_before, accounts.destination_pool.total_staked);
+        record_rewards_distributed(&mut accounts.global_state, rewards);
+
+        mint_lst(
+            &accounts.destination_pool,
+            &accounts.destination_lst_mint,
+            accounts.user_lst_account.to_account_info(),
+            &accounts.token_program,
+            lst_to_mint,
+        )?;
+
+        emit!(StakeEvent {
+            pool: accounts.destination_pool.key(),
+            user: accounts.destination_user.key(),
+            amount: received,
+            lst_minted: lst_to_mint,
+            lock_type: accounts.destination_user.lock_type,
+            label: accounts.destination_user.label,
+        });
+
+        msg!("Claimed {} rewards and staked {} into the destination pool", rewards, received);
+        Ok(())
+    }
+
+    /// View: primary rewards a claim would pay the user right now, net of the
+    /// performance fee (read-only, use via simulation)
+    pub fn get_pending_rewards(ctx: Context<GetPendingRewards>) -> Result<u64> {
+        let clock = Clock::get()?;
+
+        // Settle copies the same way a claim would, so no account is mutated
+        let mut pool = (*ctx.accounts.pool).clone();
+        let mut user = (*ctx.accounts.user).clone();
+        let pending = accrue_user_rewards(&mut pool, &mut user, clock.unix_timestamp)?;
+        let settlement = settle_rewards(&pool, pending)?;
+
+        msg!(
+            "Pending rewards: {} ({} after the performance fee)",
+            settlement.gross,
+            settlement.net
+        );
+        Ok(settlement.net)
+    }
+
+    /// Record the user's current voting weight (stake scaled by the lock
+    /// multiplier) under `snapshot_id`
+    pub fn snapshot_stake(ctx: Context<SnapshotStake>, snapshot_id: u64) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        let clock = Clock::get()?;
+
+        let weight = voting_weight(user)?;
+        let index = (user.snapshot_count % MAX_STAKE_SNAPSHOTS as u64) as usize;
+        user.stake_snapshots[index] = StakeSnapshot {
+            snapshot_id,
+            weight,
+            timestamp: clock.unix_timestamp,
+        };
+        user.snapshot_count = user.snapshot_count
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        msg!("Snapshot {}: voting weight {}", snapshot_id, weight);
+        Ok(())
+    }
+
+    /// View: voting weight recorded at the user's latest snapshot (0 if none)
+    pub fn get_voting_weight(ctx: Context<GetVotingWeight>) -> Result<u64> {
+        let weight = latest_snapshot(&ctx.accounts.user)
+            .map(|snapshot| snapshot.weight)
+            .unwrap_or(0);
+
+        msg!("Voting weight: {}", weight);
+        Ok(weight)
+    }
+
+    /// View: whether the position is still locked and for how long
+    pub fn get_unlock_info(ctx: Context<GetUnlockInfo>) -> Result<UnlockInfo> {
+        let info = unlock_info(&ctx.accounts.user, Clock::get()?.unix_timestamp);
+
+        msg!("Unlocks at {} ({}s remaining)", info.unlock_timestamp, info.seconds_remaining);
+        Ok(info)
+    }
+
+    /// View: what redeeming `lst_amount` would pay out right now. Past the lock
+    /// this mirrors `unstake` (no penalty, rewards kept); inside it, it mirrors
+    /// `emergency_unstake` (penalty withheld, rewards forfeited).
+    pub fn preview_unstake(ctx: Context<PreviewUnstake>, lst_amount: u64) -> Result<UnstakePreview> {
+        require!(lst_amount > 0, ErrorCode::InvalidAmount);
+        let clock = Clock::get()?;
+
+        // Work on copies so no account is mutated
+        let mut pool = (*ctx.accounts.pool).clone();
+        let mut user = (*ctx.accounts.user).clone();
+        renew_lock(&mut user, clock.unix_timestamp)?;
+        update_pool_rewards(&mut pool, clock.unix_timestamp)?;
+        // Reject what unstake would reject: LST not issued to this position
+        principal_for_lst(&user, lst_amount)?;
+
+        let vault_balance = ctx.accounts.pool_vault.amount.saturating_sub(pool.total_unstake_pending);
+        let preview = unstake_preview(
+            &pool,
+            &user,
+            ctx.accounts.lst_mint.supply,
+            vault_balance,
+            lst_amount,
+            clock.unix_timestamp,
+        )?;
+
+        msg!(
+            "Unstake preview: {} returned, {} penalty, {} rewards",
+            preview.principal_returned,
+            preview.penalty,
+            preview.pending_rewards
+        );
+        Ok(preview)
+    }
+
+    /// View: annualized primary reward rate in basis points of the staked value.
+    /// `reward_price_bps` is the reward token's price in stake tokens (10000 = 1:1,
+    /// the default). Returns 0 while nothing is staked.
+    pub fn get_pool_apr(ctx: Context<GetPoolApr>, reward_price_bps: Option<u64>) -> Result<u64> {
+        let pool = &ctx.accounts.pool;
+        if pool.total_staked == 0 {
+            return Ok(0);
+        }
+
+        // rewards/year * price / staked, expressed in bps; the two 10000 scales
+        // cancel. Rewards are converted to stake-mint units before dividing.
+        let rewards_per_year = (pool.current_reward_rate() as u128)
+            .checked_mul(SECONDS_PER_YEAR as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_mul(reward_price_bps.unwrap_or(BPS_SCALE) as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let apr_bps = pool.reward_to_stake_units(rewards_per_year)?
+            .checked_div(pool.total_staked as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let apr_bps = u64::try_from(apr_bps).map_err(|_| ErrorCode::MathOverflow)?;
+
+        msg!("Pool APR: {} bps", apr_bps);
+        Ok(apr_bps)
+    }
+
+    /// View: how long the reward vault lasts at the current primary rate, in
+    /// seconds (slots for slot-based pools). The rate already follows the
+    /// reward curve at the current stake. Returns 0 if nothing is emitted.
+    pub fn get_reward_runway(ctx: Context<GetRewardRunway>) -> Result<u64> {
+        let runway = reward_runway(&ctx.accounts.pool, ctx.accounts.reward_vault.amount);
+
+        msg!("Reward runway: {}", runway);
+        Ok(runway)
+    }
+
+    /// Set the base reward rate of several pools at once (global authority only).
+    /// `remaining_accounts` holds the pools, in the same order as `new_rates`.
+    /// Each pool is settled at its old rate first, so no change is retroactive.
+    pub fn batch_update_reward_rate<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BatchUpdateRewardRate<'info>>,
+        new_rates: Vec<u64>,
+    ) -> Result<()> {
+        let pools = ctx.remaining_accounts;
+        require!(
+            !pools.is_empty() && pools.len() == new_rates.len(),
+            ErrorCode::BatchLengthMismatch
+        );
+
+        let clock = Clock::get()?;
+        for (account, reward_rate) in pools.iter().zip(new_rates) {
+            let mut pool = Account::<Pool>::try_from(account)?;
+            require!(account.is_writable, ErrorCode::InvalidBatchAccounts);
+
+            let pool_key = Pubkey::create_program_address(
+                &[b"pool", pool.pool_id.as_ref(), &[pool.bump]],
+                &crate::ID,
+            )
+            .map_err(|_| ErrorCode::InvalidBatchAccounts)?;
+            require_keys_eq!(pool.key(), pool_key, ErrorCode::InvalidBatchAccounts);
+
+            update_pool_rewards(&mut pool, clock.unix_timestamp)?;
+            pool.reward_per_second = reward_rate;
+            pool.exit(&crate::ID)?;
+
+            msg!("Reward rate of pool {} set to {}", pool.key(), reward_rate);
+        }
+        Ok(())
+    }
+
+    /// Rebuild `pool.total_weighted_stake` from its positions (global authority
+    /// only). `remaining_accounts` must hold every `User` of the pool, sorted by
+    /// address, so the count must match `pool.user_count` and no position can
+    /// be skipped or counted twice.
+    pub fn recompute_weighted_stake<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RecomputeWeightedStake<'info>>,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let pool_key = pool.key();
+        let accounts = ctx.remaining_accounts;
+
+        // A partial set would silently shrink the denominator
+        require!(
+            accounts.len() as u64 == pool.user_count,
+            ErrorCode::IncompleteUserSet
+        );
+        require!(
+            accounts.windows(2).all(|pair| pair[0].key < pair[1].key),
+            ErrorCode::InvalidBatchAccounts
+        );
+
+        let mut users = Vec::with_capacity(accounts.len());
+        for account in accounts {
+            let user = Account::<User>::try_from(account)?;
+            require_keys_eq!(user.pool, pool_key, ErrorCode::PoolMismatch);
+            users.push(user.into_inner());
+        }
+
+        // Accrue what is owed under the old denominator before replacing it
+        update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
+        let previous = pool.total_weighted_stake;
+        pool.total_weighted_stake = total_user_weight(&users)?;
+
+        msg!(
+            "Weighted stake recomputed over {} positions: {} -> {}",
+            users.len(),
+            previous,
+            pool.total_weighted_stake
+        );
+        Ok(())
+    }
+
+    /// View: underlying tokens per LST, scaled by `SHARE_PRECISION`. Rewards are
+    /// paid out rather than auto-compounded into `total_staked`, so only
+    /// compounded stake and withheld penalties move the rate.
+    pub fn get_exchange_rate(ctx: Context<GetExchangeRate>) -> Result<u128> {
+        let rate = exchange_rate(&ctx.accounts.pool, ctx.accounts.lst_mint.supply)?;
+
+        msg!("Exchange rate: {}", rate);
+        Ok(rate)
+    }
+
+    /// View: protocol-wide TVL and rewards paid out, summed over every pool
+    pub fn get_global_stats(ctx: Context<GetGlobalStats>) -> Result<GlobalStats> {
+        let global_state = &ctx.accounts.global_state;
+        let stats = GlobalStats {
+            total_value_locked: global_state.total_value_locked,
+            total_rewards_distributed: global_state.total_rewards_distributed,
+        };
+
+        msg!(
+            "TVL: {}, rewards distributed: {}",
+            stats.total_value_locked,
+            stats.total_rewards_distributed
+        );
+        Ok(stats)
+    }
+
+    /// Propose a new pool authority (current pool authority only). Takes effect
+    /// once the proposed key calls `accept_pool_authority`.
+    pub fn set_pool_authority(ctx: Context<UpdatePool>, new_authority: Pubkey) -> Result<()> {
+        ctx.accounts.pool.pending_pool_authority = new_authority;
+
+        msg!("Proposed new pool authority: {}", new_authority);
+        Ok(())
+    }
+
+    /// Accept a pending pool authority proposal (pending pool authority only)
+    pub fn accept_pool_authority(ctx: Context<AcceptPoolAuthority>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        require!(
+            pool.pending_pool_authority != Pubkey::default(),
+            ErrorCode::Unauthorized
+        );
+        require_keys_eq!(
+            ctx.accounts.new_authority.key(),
+            pool.pending_pool_authority,
+            ErrorCode::Unauthorized
+        );
+
+        pool.authority = pool.pending_pool_authority;
+        pool.pending_pool_authority = Pubkey::default();
+        msg!("Pool authority transferred to: {}", pool.authority);
+        Ok(())
+    }
+
+    /// Update pool parameters (authority only)
+    pub fn update_pool(
+        ctx: Context<UpdatePool>,
+        new_reward_per_second: Option<u64>,
+        new_lock_tiers: Option<Vec<LockTier>>,
+        new_early_withdraw_penalty_bps: Option<u16>,
+        new_min_stake: Option<u64>,
+        new_max_total_staked: Option<u64>,
+        new_unstake_cooldown: Option<u64>,
+        new_reward_start_time: Option<i64>,
+        new_reward_end_time: Option<i64>,
+        new_max_users: Option<u64>,
+        new_deposit_fee_bps: Option<u16>,
+        new_treasury: Option<Pubkey>,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+
+        // Settle at the old rate and window so changes are not retroactive
+        update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
+
+        if let Some(reward_rate) = new_reward_per_second {
+            pool.reward_per_second = reward_rate;
+        }
+
+        if let Some(tiers) = new_lock_tiers {
+            pool.set_lock_tiers(&tiers)?;
+        }
+
+        if let Some(penalty_bps) = new_early_withdraw_penalty_bps {
+            require!(penalty_bps as u64 <= BPS_SCALE, ErrorCode::InvalidBasisPoints);
+            pool.early_withdraw_penalty_bps = penalty_bps;
+        }
+
+        if let Some(min_stake) = new_min_stake {
+            pool.min_stake = min_stake;
+        }
+
+        if let Some(max_total_staked) = new_max_total_staked {
+            pool.max_total_staked = max_total_staked;
+        }
+
+        if let Some(unstake_cooldown) = new_unstake_cooldown {
+            pool.unstake_cooldown = unstake_cooldown;
+        }
+
+        if new_reward_start_time.is_some() || new_reward_end_time.is_some() {
+            pool.set_reward_window(
+                new_reward_start_time.unwrap_or(pool.reward_start_time),
+                new_reward_end_time.unwrap_or(pool.reward_end_time),
+            )?;
+        }
+
+        if let Some(max_users) = new_max_users {
+            pool.max_users = max_users;
+        }
+
+        if let Some(fee_bps) = new_deposit_fee_bps {
+            require!(fee_bps as u64 <= BPS_SCALE, ErrorCode::InvalidBasisPoints);
+            pool.deposit_fee_bps = fee_bps;
+        }
+
+        if let Some(treasury) = new_treasury {
+            pool.treasury = treasury;
+        }
+
+        msg!("Pool parameters updated");
+        Ok(())
+    }
+
+    /// Configure the loyalty bonus earned by flexible stakers (authority only):
+    /// `per_period_bps` per full `period_seconds` staked, capped at `max_bps`
+    pub fn set_loyalty_bonus(
+        ctx: Context<UpdatePool>,
+        per_period_bps: u16,
+        period_seconds: u64,
+        max_bps: u16,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+
+        // Settle at the old rates before changing them
+        update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
+
+        pool.loyalty_bonus_per_period_bps = per_period_bps;
+        pool.loyalty_period_seconds = period_seconds;
+        pool.max_loyalty_bonus_bps = max_bps;
+
+        msg!(
+            "Loyalty bonus set to {} bps per {}s (max {} bps)",
+            per_period_bps,
+            period_seconds,
+            max_bps
+        );
+        Ok(())
+    }
+
+    /// Set the share of referred users' claims paid to their referrer (authority only)
+    pub fn set_referral_bonus(ctx: Context<UpdatePool>, bonus_bps: u16) -> Result<()> {
+        require!(bonus_bps as u64 <= BPS_SCALE, ErrorCode::InvalidBasisPoints);
+        ctx.accounts.pool.referral_bonus_bps = bonus_bps;
+
+        msg!("Referral bonus set to {} bps", bonus_bps);
+        Ok(())
+    }
+
+    /// Replace the TVL reward curve (authority only). Each breakpoint's rate
+    /// applies once `total_staked` reaches its threshold; below the first, the
+    /// base `reward_per_second` applies. An empty curve disables it.
+    pub fn set_reward_curve(ctx: Context<UpdatePool>, breakpoints: Vec<RewardBreakpoint>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+
+        // Settle at the old curve before changing it
+        update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
+        pool.set_reward_curve(&breakpoints)?;
+
+        msg!("Reward curve set with {} breakpoints", breakpoints.len());
+        Ok(())
+    }
+
+    /// Freeze or resume reward emission (authority only). Deposits and
+    /// withdrawals are unaffected; nothing accrues while paused.
+    pub fn set_rewards_paused(ctx: Context<UpdatePool>, paused: bool) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+
+        // Settle up to now under the old setting
+        update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
+        pool.rewards_paused = paused;
+
+        msg!("Rewards paused: {}", paused);
+        Ok(())
+    }
+
+    /// Take `fee_bps` of every primary reward payout for `fee_recipient`
+    /// (authority only). A non-zero fee needs the recipient's reward token
+    /// account so payouts can't be left without a destination.
+    pub fn set_performance_fee(
+        ctx: Context<SetPerformanceFee>,
+        fee_bps: u16,
+        fee_recipient: Pubkey,
+    ) -> Result<()> {
+        require!(fee_bps as u64 <= BPS_SCALE, ErrorCode::InvalidBasisPoints);
+        if fee_bps > 0 {
+            let fee_account = ctx.accounts.fee_recipient_reward_account
+                .as_ref()
+                .ok_or(ErrorCode::InvalidFeeRecipient)?;
+            require_keys_eq!(fee_account.owner, fee_recipient, ErrorCode::InvalidFeeRecipient);
+        }
+        let pool = &mut ctx.accounts.pool;
+        pool.performance_fee_bps = fee_bps;
+        pool.fee_recipient = fee_recipient;
+
+        msg!("Performance fee set to {} bps for {}", fee_bps, fee_recipient);
+        Ok(())
+    }
+
+    /// Schedule a primary-reward boost (authority only): emission runs at
+    /// `multiplier` (10000 = 1x) between `start` and `end`. A zero multiplier
+    /// cancels any boost.
+    pub fn set_reward_boost(
+        ctx: Context<UpdatePool>,
+        multiplier: u16,
+        start: i64,
+        end: i64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+
+        // Settle up to now under the old boost
+        update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
+        pool.set_reward_boost(multiplier, start, end)?;
+
+        msg!("Reward boost of {} from {} to {}", multiplier, start, end);
+        Ok(())
+    }
+
+    /// Switch primary emission between time-based (`reward_per_second`) and
+    /// slot-based (`reward_per_slot`) accrual (authority only)
+    pub fn set_slot_based_rewards(
+        ctx: Context<UpdatePool>,
+        enabled: bool,
+        reward_per_slot: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let clock = Clock::get()?;
+
+        // Settle under the old mode, then start counting slots from here
+        update_pool_rewards(pool, clock.unix_timestamp)?;
+        pool.use_slot_based = enabled;
+        pool.reward_per_slot = reward_per_slot;
+        pool.last_update_slot = clock.slot;
+
+        msg!("Slot-based rewards: {} ({} per slot)", enabled, reward_per_slot);
+        Ok(())
+    }
+
+    /// Cap the primary rewards this pool will ever emit (authority only).
+    /// Accrual stops once `total_reward_distributed` reaches `cap`; 0 removes
+    /// the cap.
+    pub fn set_reward_emission_cap(ctx: Context<UpdatePool>, cap: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+
+        // Settle under the old cap so it governs everything emitted so far
+        update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
+        pool.reward_emission_cap = cap;
+
+        msg!("Reward emission cap set to {} ({} emitted)", cap, pool.total_reward_distributed);
+        Ok(())
+    }
+
+    /// Restrict staking to whitelisted wallets (authority only)
+    pub fn set_whitelist_enabled(ctx: Context<UpdatePool>, enabled: bool) -> Result<()> {
+        ctx.accounts.pool.whitelist_enabled = enabled;
+
+        msg!("Whitelist enabled: {}", enabled);
+        Ok(())
+    }
+
+    /// Allow `wallet` to stake while the pool's whitelist is enabled (authority only)
+    pub fn add_to_whitelist(ctx: Context<AddToWhitelist>, wallet: Pubkey) -> Result<()> {
+        let whitelist = &mut ctx.accounts.whitelist;
+        whitelist.bump = ctx.bumps.whitelist;
+        whitelist.pool = ctx.accounts.pool.key();
+        whitelist.wallet = wallet;
+
+        msg!("Whitelisted {}", wallet);
+        Ok(())
+    }
+
+    /// Revoke a wallet's whitelist entry (authority only). Existing stake is
+    /// unaffected; only new deposits are blocked.
+    pub fn remove_from_whitelist(ctx: Context<RemoveFromWhitelist>) -> Result<()> {
+        msg!("Removed {} from the whitelist", ctx.accounts.whitelist.wallet);
+        Ok(())
+    }
+
+    /// Set the minimum seconds between a user's claims (authority only, 0 = none)
+    pub fn set_min_claim_interval(ctx: Context<UpdatePool>, seconds: u64) -> Result<()> {
+        ctx.accounts.pool.min_claim_interval = seconds;
+
+        msg!("Min claim interval set to {}s", seconds);
+        Ok(())
+    }
+
+    /// Set the reward vault balance below which claims raise the low-balance
+    /// flag (authority only, 0 = never)
+    pub fn set_low_balance_threshold(ctx: Context<UpdatePool>, threshold: u64) -> Result<()> {
+        ctx.accounts.pool.low_balance_threshold = threshold;
+
+        msg!("Low reward balance threshold set to {}", threshold);
+        Ok(())
+    }
+
+    /// Cap the underlying a single `unstake` or `request_unstake` may withdraw
+    /// (authority only, 0 = unlimited). `emergency_unstake` is never limited.
+    pub fn set_max_unstake_per_tx(ctx: Context<UpdatePool>, max_amount: u64) -> Result<()> {
+        ctx.accounts.pool.max_unstake_per_tx = max_amount;
+
+        msg!("Max unstake per transaction set to {}", max_amount);
+        Ok(())
+    }
+
+    /// Cap any single position's share of emissions at `max_bps` of the pool
+    /// (authority only, 0 = uncapped). Clamped rewards stay in the vault.
+    pub fn set_max_reward_share(ctx: Context<UpdatePool>, max_bps: u16) -> Result<()> {
+        require!(max_bps as u64 <= BPS_SCALE, ErrorCode::InvalidBasisPoints);
+        let pool = &mut ctx.accounts.pool;
+
+        // Settle at the old cap before changing it
+        update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
+        pool.max_reward_share_bps = max_bps;
+
+        msg!("Max reward share set to {} bps", max_bps);
+        Ok(())
+    }
+
+    /// Cap the effective reward multiplier (lock, loyalty and NFT bonuses
+    /// combined) at `max_multiplier` (authority only, 0 = uncapped)
+    pub fn set_max_bonus_multiplier(ctx: Context<UpdatePool>, max_multiplier: u16) -> Result<()> {
+        require!(
+            max_multiplier == 0 || max_multiplier as u64 >= BPS_SCALE,
+            ErrorCode::InvalidBonusMultiplier
+        );
+        let pool = &mut ctx.accounts.pool;
+
+        // Settle at the old cap before changing it
+        update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
+        pool.max_bonus_multiplier = max_multiplier;
+
+        msg!("Max bonus multiplier set to {}", max_multiplier);
+        Ok(())
+    }
+
+    /// Configure the NFT boost (authority only): holders of a verified NFT from
+    /// `collection` gain `boost_bps` on their multiplier for `duration` seconds
+    pub fn set_nft_boost(
+        ctx: Context<UpdatePool>,
+        collection: Pubkey,
+        boost_bps: u16,
+        duration: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.boost_nft_collection = collection;
+        pool.nft_boost_bps = boost_bps;
+        pool.nft_boost_duration = duration;
+
+        msg!("NFT boost set to {} bps for {}s (collection {})", boost_bps, duration, collection);
+        Ok(())
+    }
+
+    /// Boost the signer's position by holding an NFT from the pool's collection.
+    /// Re-applying refreshes the expiry. While a boost is active its NFT is
+    /// locked to the position, so one NFT cannot boost two positions in a pool.
+    pub fn apply_nft_boost(ctx: Context<ApplyNftBoost>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let user = &mut ctx.accounts.user;
+        let clock = Clock::get()?;
+        renew_lock(user, clock.unix_timestamp)?;
+
+        // The metadata PDA is derived from the held mint, so its collection is the NFT's
+        let collection = ctx.accounts.nft_metadata.collection
+            .as_ref()
+            .ok_or(ErrorCode::NotNftHolder)?;
+        require!(
+            pool.boost_nft_collection != Pubkey::default()
+                && collection.verified
+                && collection.key == pool.boost_nft_collection,
+            ErrorCode::NotNftHolder
+        );
+
+        // Claim the NFT for this position unless another one's boost is still
+        // live (a lock created just now has expired at 0)
+        let nft_mint = ctx.accounts.nft_token_account.mint;
+        let nft_lock = &mut ctx.accounts.nft_boost_lock;
+        require!(
+            nft_lock.user == user.key() || clock.unix_timestamp >= nft_lock.expires_at,
+            ErrorCode::NftBoostInUse
+        );
+
+        // Settle at the current multiplier before boosting it
+        update_pool_rewards(pool, clock.unix_timestamp)?;
+        let pending = pending_rewards(pool, user)?;
+        user.unclaimed_rewards = user.unclaimed_rewards
+            .checked_add(pending)
+            .ok_or(ErrorCode::MathOverflow)?;
+        settle_reward_streams(pool, user)?;
+        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        user.reward_debt = reward_debt_for(pool, user.amount)?;
+        sync_reward_stream_debts(pool, user)?;
+
+        let duration = i64::try_from(pool.nft_boost_duration).map_err(|_| ErrorCode::MathOverflow)?;
+        user.nft_boost_bps = pool.nft_boost_bps;
+        user.nft_boost_expires_at = clock.unix_timestamp
+            .checked_add(duration)
+            .ok_or(ErrorCode::MathOverflow)?;
+        user.nft_boost_mint = nft_mint;
+        // The boost must be in the denominator before it earns
+        reweigh_stake(pool, user, clock.unix_timestamp)?;
+
+        nft_lock.bump = ctx.bumps.nft_boost_lock;
+        nft_lock.pool = pool.key();
+        nft_lock.mint = nft_mint;
+        nft_lock.user = user.key();
+        nft_lock.expires_at = user.nft_boost_expires_at;
+
+        msg!(
+            "Applied {} bps NFT boost until {}",
+            user.nft_boost_bps,
+            user.nft_boost_expires_at
+        );
+        Ok(())
+    }
+
+    /// Close an empty pool (authority only), returning the rent of the pool and
+    /// its vaults to the authority. Every position must be closed and every LST
+    /// burned first. `remaining_accounts` holds the vault of each extra reward
+    /// stream, in order; they must be empty and are closed too.
+    pub fn close_pool<'info>(ctx: Context<'_, '_, 'info, 'info, ClosePool<'info>>) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+
+        let streams = pool.active_reward_streams();
+        require!(
+            ctx.remaining_accounts.len() == streams.len(),
+            ErrorCode::InvalidRewardStream
+        );
+        for (stream, account) in streams.iter().zip(ctx.remaining_accounts.iter()) {
+            let stream_vault = InterfaceAccount::<TokenAccount>::try_from(account)?;
+            require_keys_eq!(stream_vault.key(), stream.reward_vault, ErrorCode::InvalidRewardStream);
+            require!(stream_vault.amount == 0, ErrorCode::PoolNotEmpty);
+            close_pool_vault(
+                pool,
+                &stream_vault,
+                ctx.accounts.authority.to_account_info(),
+                &ctx.accounts.token_program,
+            )?;
+        }
+
+        close_pool_vault(
+            pool,
+            &ctx.accounts.pool_vault,
+            ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.token_program,
+        )?;
+        close_pool_vault(
+            pool,
+            &ctx.accounts.reward_vault,
+            ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.token_program,
+        )?;
+
+        let global_state = &mut ctx.accounts.global_state;
+        global_state.pool_count = global_state.pool_count
+            .checked_sub(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        msg!("Pool closed: {}", String::from_utf8_lossy(&pool.pool_id));
+        Ok(())
+    }
+
+    /// Move the signer's whole position (stake, lock state, banked rewards and
+    /// the LST issued to it) to `new_owner`'s position `new_position_index`,
+    /// closing the old user account
+    pub fn transfer_position(
+        ctx: Context<TransferPosition>,
+        new_owner: Pubkey,
+        new_position_index: u8,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let user = &mut ctx.accounts.user;
+        let clock = Clock::get()?;
+        renew_lock(user, clock.unix_timestamp)?;
+
+        // Bank everything earned so far; it moves with the position
+        update_pool_rewards(pool, clock.unix_timestamp)?;
+        let pending = pending_rewards(pool, user)?;
+        user.unclaimed_rewards = user.unclaimed_rewards
+            .checked_add(pending)
+            .ok_or(ErrorCode::MathOverflow)?;
+        settle_reward_streams(pool, user)?;
+        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        user.reward_debt = reward_debt_for(pool, user.amount)?;
+        sync_reward_stream_debts(pool, user)?;
+        reweigh_stake(pool, user, clock.unix_timestamp)?;
+
+        let mut position = (**user).clone();
+        position.bump = ctx.bumps.new_user;
+        position.owner = new_owner;
+        position.claim_delegate = None;
+        position.position_index = new_position_index;
+        ctx.accounts.new_user.set_inner(position);
+
+        // The position's LST is needed to redeem the stake; other positions
+        // sharing the signer's LST account keep theirs
+        let lst_amount = ctx.accounts.new_user.lst_minted;
+        if lst_amount > 0 {
+            let transfer_accounts = TransferChecked {
+                from: ctx.accounts.user_lst_account.to_account_info(),
+                to: ctx.accounts.new_owner_lst_account.to_account_info(),
+                authority: ctx.accounts.authority.to_account_info(),
+                mint: ctx.accounts.lst_mint.to_account_info(),
+            };
+
+            let transfer_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                transfer_accounts,
+            );
+
+            token_interface::transfer_checked(transfer_ctx, lst_amount, ctx.accounts.lst_mint.decimals)?;
+        }
+
+        msg!("Transferred position of {} tokens to {}", ctx.accounts.new_user.amount, new_owner);
+        Ok(())
+    }
+
+    /// Carve `amount` of the signer's stake into `new_owner`'s position
+    /// `new_position_index`. The new position keeps the lock state, takes a
+    /// pro-rata share of the banked rewards and the LST worth `amount` at the
+    /// current exchange rate.
+    pub fn split_position(
+        ctx: Context<SplitPosition>,
+        new_owner: Pubkey,
+        new_position_index: u8,
+        amount: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let user = &mut ctx.accounts.user;
+        let clock = Clock::get()?;
+        renew_lock(user, clock.unix_timestamp)?;
+
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(amount < user.amount, ErrorCode::InsufficientStake);
+        require!(
+            pool.max_users == 0 || pool.user_count < pool.max_users,
+            ErrorCode::MaxUsersReached
+        );
+        pool.user_count = pool.user_count.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+        // Bank everything earned so far before dividing it
+        update_pool_rewards(pool, clock.unix_timestamp)?;
+        let pending = pending_rewards(pool, user)?;
+        user.unclaimed_rewards = user.unclaimed_rewards
+            .checked_add(pending)
+            .ok_or(ErrorCode::MathOverflow)?;
+        settle_reward_streams(pool, user)?;
+        user.last_reward_claim_timestamp = clock.unix_timestamp;
+
+        let total = user.amount;
+        let pro_rata = |value: u64| -> Result<u64> {
+            let share = (value as u128)
+                .checked_mul(amount as u128)
+                .ok_or(ErrorCode::MathOverflow)?
+                / total as u128;
+            u64::try_from(share).map_err(|_| ErrorCode::MathOverflow.into())
+        };
+        // Rounded against the signer; the kept half must still hold some LST
+        let lst_to_move = lst_for_amount_rounded_up(pool, ctx.accounts.lst_mint.supply, amount)?;
+        require!(lst_to_move < user.lst_minted, ErrorCode::InsufficientStake);
+
+        // The new position shares the lock; owner-specific state starts fresh
+        let mut position = User {
+            bump: ctx.bumps.new_user,
+            owner: new_owner,
+            amount,
+            unclaimed_rewards: pro_rata(user.unclaimed_rewards)?,
+            weighted_stake: 0,
+            unstake_request: UnstakeRequest::default(),
+            nft_boost_bps: 0,
+            nft_boost_expires_at: 0,
+            nft_boost_mint: Pubkey::default(),
+            stake_snapshots: [StakeSnapshot::default(); MAX_STAKE_SNAPSHOTS],
+            snapshot_count: 0,
+            referrer: Pubkey::default(),
+            auto_renew: false,
+            label: [0; 16],
+            lifetime_rewards_claimed: 0,
+            claim_delegate: None,
+            position_index: new_position_index,
+            lst_minted: lst_to_move,
+            ..(**user).clone()
+        };
+        for (moved, kept) in position.reward_streams.iter_mut().zip(user.reward_streams.iter()) {
+            moved.unclaimed_rewards = pro_rata(kept.unclaimed_rewards)?;
+        }
+
+        user.amount -= amount;
+        user.lst_minted -= lst_to_move;
+        user.unclaimed_rewards -= position.unclaimed_rewards;
+        for (kept, moved) in user.reward_streams.iter_mut().zip(position.reward_streams.iter()) {
+            kept.unclaimed_rewards -= moved.unclaimed_rewards;
+        }
+
+        for side in [&mut **user, &mut position] {
+            side.reward_debt = reward_debt_for(pool, side.amount)?;
+            sync_reward_stream_debts(pool, side)?;
+            reweigh_stake(pool, side, clock.unix_timestamp)?;
+        }
+        ctx.accounts.new_user.set_inner(position);
+
+        if lst_to_move > 0 {
+            let transfer_accounts = TransferChecked {
+                from: ctx.accounts.user_lst_account.to_account_info(),
+                to: ctx.accounts.new_owner_lst_account.to_account_info(),
+                authority: ctx.accounts.authority.to_account_info(),
+                mint: ctx.accounts.lst_mint.to_account_info(),
+            };
+
+            let transfer_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                transfer_accounts,
+            );
+
+            token_interface::transfer_checked(transfer_ctx, lst_to_move, ctx.accounts.lst_mint.decimals)?;
+        }
+
+        msg!("Split {} tokens into a new position for {}", amount, new_owner);
+        Ok(())
+    }
+
+    /// Close user account, paying out pending rewards (net of the performance
+    /// fee) and the remaining stake. Redeems the LST issued to the position
+    /// plus any queued unstake whose cooldown has elapsed. Extra reward streams
+    /// must be claimed beforehand.
+    pub fn close_user_account(ctx: Context<CloseUserAccount>) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        let pool = &mut ctx.accounts.pool;
+        let clock = Clock::get()?;
+        renew_lock(user, clock.unix_timestamp)?;
+
+        // Check lock period
+        require!(
+            clock.unix_timestamp >= user.lock_end_timestamp,
+            ErrorCode::StillInLockPeriod
+        );
+
+        // Settle rewards up to now
+        update_pool_rewards(pool, clock.unix_timestamp)?;
+        let rewards = pending_rewards(pool, user)?
+            .checked_add(user.unclaimed_rewards)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(
+            ctx.accounts.reward_vault.amount >= rewards,
+            ErrorCode::InsufficientRewardFunds
+        );
+        let settlement = settle_rewards(pool, rewards)?;
+
+        // Closing would forfeit extra-stream rewards; claim_rewards pays them
+        settle_reward_streams(pool, user)?;
+        require!(
+            user.reward_streams.iter().all(|position| position.unclaimed_rewards == 0),
+            ErrorCode::UserNotEmpty
+        );
+
+        // Redeem the position's LST at the current exchange rate; other
+        // positions sharing the signer's LST account keep theirs
+        let lst_amount = user.lst_minted;
+        let mut principal = 0;
+        if lst_amount > 0 {
+            require!(pool.unstake_cooldown == 0, ErrorCode::CooldownRequired);
+
+            let lst_supply = ctx.accounts.lst_mint.supply;
+            let vault_balance = ctx.accounts.pool_vault.amount.saturating_sub(pool.total_unstake_pending);
+            principal = redeemable_amount(pool, lst_supply, vault_balance, lst_amount)?;
+            require!(vault_balance >= principal, ErrorCode::InsufficientVaultBalance);
+
+            let staked_before = pool.total_staked;
+            pool.total_staked = if lst_amount == lst_supply {
+                0
+            } else {
+                pool.total_staked
+                    .checked_sub(principal)
+                    .ok_or(ErrorCode::MathOverflow)?
+            };
+            record_tvl_change(&mut ctx.accounts.global_state, staked_before, pool.total_staked);
+        }
+
+        // Release a queued unstake that is ready
+        let queued = user.unstake_request.amount;
+        if queued > 0 {
+            require!(
+                clock.unix_timestamp >= user.unstake_request.available_at,
+                ErrorCode::CooldownNotElapsed
+            );
+            pool.total_unstake_pending = pool.total_unstake_pending
+                .checked_sub(queued)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        let amount = principal.checked_add(queued).ok_or(ErrorCode::MathOverflow)?;
+
+        pool.user_count = pool.user_count.saturating_sub(1);
+        pool.total_weighted_stake = pool.total_weighted_stake
+            .checked_sub(user.weighted_stake)
+            .ok_or(ErrorCode::MathOverflow)?;
+        record_rewards_distributed(&mut ctx.accounts.global_state, rewards);
+
+        if lst_amount > 0 {
+            burn_lst(
+                &ctx.accounts.lst_mint,
+                &ctx.accounts.user_lst_account,
+                &ctx.accounts.authority,
+                &ctx.accounts.token_program,
+                lst_amount,
+            )?;
+        }
+
+        if amount > 0 {
+            transfer_from_pool(
+                &ctx.accounts.pool,
+                &ctx.accounts.pool_vault,
+                ctx.accounts.stake_token_account.to_account_info(),
+                &ctx.accounts.stake_mint,
+                &ctx.accounts.token_program,
+                amount,
+            )?;
+        }
+
+        if settlement.net > 0 {
+            transfer_from_pool(
+                &ctx.accounts.pool,
+                &ctx.accounts.reward_vault,
+                ctx.accounts.user_reward_account.to_account_info(),
+                &ctx.accounts.reward_mint,
+                &ctx.accounts.token_program,
+                settlement.net,
+            )?;
+        }
+        pay_performance_fee(
+            &ctx.accounts.pool,
+            &ctx.accounts.reward_vault,
+            ctx.accounts.fee_recipient_reward_account.as_ref().map(|account| account.to_account_info()),
+            &ctx.accounts.reward_mint,
+            &ctx.accounts.token_program,
+            settlement.performance_fee,
+        )?;
+
+        // Close user account and return rent
+        ctx.accounts.user.close(ctx.accounts.user_wallet.to_account_info())?;
+
+        msg!("User account closed, {} tokens withdrawn, {} rewards paid", amount, settlement.net);
+        Ok(())
+    }
+
+    /// Close an abandoned user account at `user_key` (pool authority only).
+    /// The position must hold no stake, queued unstake or rewards; the rent
+    /// goes back to the owner recorded on the account.
+    pub fn admin_close_user(ctx: Context<AdminCloseUser>, user_key: Pubkey) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let user = &mut ctx.accounts.user;
+
+        update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
+        let pending = pending_rewards(pool, user)?;
+        settle_reward_streams(pool, user)?;
+        require!(
+            user.amount == 0
+                && pending == 0
+                && user.unclaimed_rewards == 0
+                && user.unstake_request.amount == 0
+                && user.reward_streams.iter().all(|position| position.unclaimed_rewards == 0),
+            ErrorCode::UserNotEmpty
+        );
+
+        pool.user_count = pool.user_count.saturating_sub(1);
+        pool.total_weighted_stake = pool.total_weighted_stake
+            .checked_sub(user.weighted_stake)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        msg!("Closed abandoned user account {} (owner {})", user_key, user.owner);
+        Ok(())
+    }
+}
+
+// ============ Reward Math ============
+
+/// Seconds from `then` to `now`, or 0 when `then` is not in the past
+fn elapsed_since(now: i64, then: i64) -> u64 {
+    now.saturating_sub(then).max(0) as u64
+}
+
+/// Advance the pool's reward accumulator to `now` (and, for slot-based
+/// pools, to the current slot)
+fn update_pool_rewards(pool: &mut Pool, now: i64) -> Result<()> {
+    let slot = if pool.use_slot_based { Clock::get()?.slot } else { 0 };
+    accrue_rewards(pool, now, slot)
+}
+
+/// Advance the pool's reward accumulator to `now` and `slot`. Slot-based
+/// pools emit the primary reward per slot and ignore the time-denominated
+/// reward window and boost; extra streams always run on time.
+fn accrue_rewards(pool: &mut Pool, now: i64, slot: u64) -> Result<()> {
+    // A paused pool skips the window entirely; it is not owed later
+    let emitting = pool.total_weighted_stake > 0 && !pool.rewards_paused;
+
+    if pool.use_slot_based {
+        let slots_elapsed = slot.saturating_sub(pool.last_update_slot);
+        if slots_elapsed > 0 && emitting {
+            let (rewards_to_distribute, reward_per_share) =
+                emissions(pool.reward_per_slot, slots_elapsed, pool.total_weighted_stake)?;
+            emit_primary(pool, rewards_to_distribute, reward_per_share)?;
+        }
+        pool.last_update_slot = pool.last_update_slot.max(slot);
+    }
+
+    // Only the part of [last update, now] inside the reward window accrues
+    let from = pool.last_update_timestamp.max(pool.reward_start_time);
+    let to = if pool.reward_end_time > 0 {
+        now.min(pool.reward_end_time)
+    } else {
+        now
+    };
+
+    // A stored timestamp ahead of the clock (validator skew) accrues nothing
+    // rather than underflowing into an enormous elapsed time
+    let time_elapsed = elapsed_since(to, from);
+    if time_elapsed > 0 && emitting && !pool.use_slot_based {
+        // Seconds inside the boost window emit at the boosted primary rate
+        let boosted = pool.boosted_seconds(from, to);
+        let rate = pool.current_reward_rate();
+        let boosted_rate = u64::try_from(
+            rate as u128 * pool.boost_multiplier as u128 / BPS_SCALE as u128,
+        )
+        .map_err(|_| ErrorCode::MathOverflow)?;
+        let (base_rewards, base_per_share) =
+            emissions(rate, time_elapsed - boosted, pool.total_weighted_stake)?;
+        let (boost_rewards, boost_per_share) =
+            emissions(boosted_rate, boosted, pool.total_weighted_stake)?;
+        let reward_per_share = base_per_share
+            .checked_add(boost_per_share)
+            .ok_or(ErrorCode::MathOverflow)?;
+        emit_primary(pool, base_rewards + boost_rewards, reward_per_share)?;
+    }
+    if time_elapsed > 0 && emitting {
+        let total_weighted_stake = pool.total_weighted_stake;
+        let stream_count = pool.reward_stream_count as usize;
+        for stream in pool.reward_streams[..stream_count].iter_mut() {
+            let (_, reward_per_share) =
+                emissions(stream.reward_per_second, time_elapsed, total_weighted_stake)?;
+            stream.acc_reward_per_share = stream.acc_reward_per_share
+                .checked_add(reward_per_share)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+    }
+    // Advance even when nothing is staked, so the next staker does not
+    // collect emissions from the empty span
+    pool.last_update_timestamp = pool.last_update_timestamp.max(now);
+    Ok(())
+}
+
+/// Credit a primary emission to the accumulator. Once `total_reward_distributed`
+/// reaches the pool's emission cap, only the remainder up to the cap accrues.
+fn emit_primary(pool: &mut Pool, rewards: u128, reward_per_share: u128) -> Result<()> {
+    let (rewards, reward_per_share) = if pool.reward_emission_cap > 0 {
+        let remaining = pool.reward_emission_cap.saturating_sub(pool.total_reward_distributed) as u128;
+        if rewards > remaining {
+            let per_share = remaining
+                .checked_mul(SHARE_PRECISION)
+                .ok_or(ErrorCode::MathOverflow)?
+                / pool.total_weighted_stake as u128;
+            (remaining, per_share)
+        } else {
+            (rewards, reward_per_share)
+        }
+    } else {
+        (rewards, reward_per_share)
+    };
+    pool.acc_reward_per_share = pool.acc_reward_per_share
+        .checked_add(reward_per_share)
+        .ok_or(ErrorCode::MathOverflow)?;
+    // Saturate rather than halting accrual on an uncapped pool
+    pool.total_reward_distributed = pool.total_reward_distributed
+        .saturating_add(u64::try_from(rewards).unwrap_or(u64::MAX));
+    Ok(())
+}
+
+/// Rewards emitted at `rate` over `elapsed` seconds, and their increment per
+/// unit of weighted stake
+fn emissions(rate: u64, elapsed: u64, total_weight: u64) -> Result<(u128, u128)> {
+    let rewards = (rate as u128)
+        .checked_mul(elapsed as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let per_share = rewards
+        .checked_mul(SHARE_PRECISION)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(total_weight as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok((rewards, per_share))
+}
+
+/// `amount * acc_reward_per_share`, unscaled and rounded down
+fn debt_at(acc_reward_per_share: u128, amount: u64) -> Result<u128> {
+    Ok((amount as u128)
+        .checked_mul(acc_reward_per_share)
+        .ok_or(ErrorCode::MathOverflow)?
+        / SHARE_PRECISION)
+}
+
+/// `amount * acc_reward_per_share`, unscaled and rounded up. Stored debts use
+/// this so that a floored accrual minus the debt never rounds a payout up.
+fn debt_at_rounded_up(acc_reward_per_share: u128, amount: u64) -> Result<u128> {
+    Ok((amount as u128)
+        .checked_mul(acc_reward_per_share)
+        .ok_or(ErrorCode::MathOverflow)?
+        .div_ceil(SHARE_PRECISION))
+}
+
+/// Rewards accrued on `amount` since `reward_debt`, scaled by the lock bonus.
+/// Every division truncates, so payouts never exceed what was emitted.
+fn accrued_since(acc_reward_per_share: u128, amount: u64, reward_debt: u128, bonus_multiplier: u128) -> Result<u64> {
+    // The rounded-up debt may sit one unit above a fresh, floored accrual
+    let accrued = debt_at(acc_reward_per_share, amount)?.saturating_sub(reward_debt);
+    let boosted = accrued
+        .checked_mul(bonus_multiplier)
+        .ok_or(ErrorCode::MathOverflow)?
+        / BPS_SCALE as u128;
+    u64::try_from(boosted).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// Reward debt for `amount` at the pool's current accumulator
+fn reward_debt_for(pool: &Pool, amount: u64) -> Result<u128> {
+    debt_at_rounded_up(pool.acc_reward_per_share, amount)
+}
+
+/// Rewards accrued by `user` since its last settlement, including lock bonus
+fn pending_rewards(pool: &Pool, user: &User) -> Result<u64> {
+    let pending = accrued_since(pool.acc_reward_per_share, user.amount, user.reward_debt, effective_multiplier(pool, user))?;
+    Ok(cap_reward_share(pool, user.amount, pending))
+}
+
+/// Scale down `rewards` earned on `amount` so the position counts for at most
+/// `max_reward_share_bps` of the pool's current stake
+fn cap_reward_share(pool: &Pool, amount: u64, rewards: u64) -> u64 {
+    if pool.max_reward_share_bps == 0 || amount == 0 {
+        return rewards;
+    }
+    let cap = pool.total_staked as u128 * pool.max_reward_share_bps as u128 / BPS_SCALE as u128;
+    if amount as u128 <= cap {
+        return rewards;
+    }
+    (rewards as u128 * cap / amount as u128) as u64
+}
+
+/// Lock multiplier plus the loyalty bonus a flexible staker had earned when last
+/// settled and any unexpired NFT boost, as of the pool's last update (10000 =
+/// 1x), clamped to `pool.max_bonus_multiplier`. Past lock expiry the lock bonus
+/// only covers the share of the unsettled span (since
+/// `last_reward_claim_timestamp`) that fell before `lock_end_timestamp`, unless
+/// the lock auto-renews. Never exceeds the multiplier `stake_weight` recorded at
+/// that settlement, so payouts stay within emissions.
+fn effective_multiplier(pool: &Pool, user: &User) -> u128 {
+    let mut multiplier = user.bonus_multiplier as u128;
+
+    if user.lock_end_timestamp > 0
+        && !user.auto_renew
+        && pool.last_update_timestamp > user.lock_end_timestamp
+    {
+        let base = BPS_SCALE as u128;
+        let lock_bonus = multiplier.saturating_sub(base);
+        let span = elapsed_since(pool.last_update_timestamp, user.last_reward_claim_timestamp);
+        let locked = elapsed_since(user.lock_end_timestamp, user.last_reward_claim_timestamp).min(span);
+        multiplier = if span > 0 {
+            base + lock_bonus * locked as u128 / span as u128
+        } else {
+            base
+        };
+    }
+
+    // Loyalty periods completed since the last settlement count from the next one
+    multiplier += loyalty_bonus(pool, user, user.last_reward_claim_timestamp);
+
+    if pool.last_update_timestamp < user.nft_boost_expires_at {
+        multiplier += user.nft_boost_bps as u128;
+    }
+
+    if pool.max_bonus_multiplier > 0 {
+        multiplier = multiplier.min(pool.max_bonus_multiplier as u128);
+    }
+
+    multiplier
+}
+
+/// Loyalty bonus (bps) a flexible position has earned by `at`, capped at
+/// `pool.max_loyalty_bonus_bps`; locked positions earn none
+fn loyalty_bonus(pool: &Pool, user: &User, at: i64) -> u128 {
+    if user.lock_end_timestamp != 0 || pool.loyalty_period_seconds == 0 {
+        return 0;
+    }
+    let staked_for = elapsed_since(at, user.lock_start_timestamp);
+    let periods = (staked_for / pool.loyalty_period_seconds) as u128;
+    periods
+        .saturating_mul(pool.loyalty_bonus_per_period_bps as u128)
+        .min(pool.max_loyalty_bonus_bps as u128)
+}
+
+/// Bank rewards accrued on each extra reward stream since the user's last settlement
+fn settle_reward_streams(pool: &Pool, user: &mut User) -> Result<()> {
+    for (stream, position) in pool.active_reward_streams().iter().zip(user.reward_streams.iter_mut()) {
+        let pending = accrued_since(
+            stream.acc_reward_per_share,
+            user.amount,
+            position.reward_debt,
+            effective_multiplier(pool, user),
+        )?;
+        let pending = cap_reward_share(pool, user.amount, pending);
+        position.unclaimed_rewards = position.unclaimed_rewards
+            .checked_add(pending)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    Ok(())
+}
+
+/// Reset each extra stream's reward debt to the user's current amount
+fn sync_reward_stream_debts(pool: &Pool, user: &mut User) -> Result<()> {
+    for (stream, position) in pool.active_reward_streams().iter().zip(user.reward_streams.iter_mut()) {
+        position.reward_debt = debt_at_rounded_up(stream.acc_reward_per_share, user.amount)?;
+    }
+    Ok(())
+}
+
+/// Weight `user` contributes to the reward denominator at `now`: stake scaled
+/// by the lock multiplier while the lock is active (or auto-renews), plus the
+/// loyalty bonus and any unexpired NFT boost, clamped like `effective_multiplier`
+fn stake_weight(pool: &Pool, user: &User, now: i64) -> Result<u64> {
+    let lock_expired = user.lock_end_timestamp > 0 && !user.auto_renew && now >= user.lock_end_timestamp;
+    let mut multiplier = if lock_expired {
+        BPS_SCALE as u128
+    } else {
+        user.bonus_multiplier as u128
+    };
+    multiplier += loyalty_bonus(pool, user, now);
+    if now < user.nft_boost_expires_at {
+        multiplier += user.nft_boost_bps as u128;
+    }
+    if pool.max_bonus_multiplier > 0 {
+        multiplier = multiplier.min(pool.max_bonus_multiplier as u128);
+    }
+
+    let weight = (user.amount as u128)
+        .checked_mul(multiplier)
+        .ok_or(ErrorCode::MathOverflow)?
+        / BPS_SCALE as u128;
+    u64::try_from(weight).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// Replace the user's contribution to `pool.total_weighted_stake` with its
+/// current weight. Call after any change to the position's amount, lock or
+/// bonuses, and at every settlement (loyalty bonuses grow between them).
+fn reweigh_stake(pool: &mut Pool, user: &mut User, now: i64) -> Result<()> {
+    let weight = stake_weight(pool, user, now)?;
+    pool.total_weighted_stake = pool.total_weighted_stake
+        .checked_sub(user.weighted_stake)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_add(weight)
+        .ok_or(ErrorCode::MathOverflow)?;
+    user.weighted_stake = weight;
+    Ok(())
+}
+
+/// Units of time (seconds, or slots for slot-based pools) `vault_balance`
+/// covers at the pool's current primary rate; 0 when nothing is emitted
+fn reward_runway(pool: &Pool, vault_balance: u64) -> u64 {
+    let rate = if pool.use_slot_based {
+        pool.reward_per_slot
+    } else {
+        pool.current_reward_rate()
+    };
+    vault_balance.checked_div(rate).unwrap_or(0)
+}
+
+/// Sum of the positions' recorded contributions to `pool.total_weighted_stake`
+fn total_user_weight(users: &[User]) -> Result<u64> {
+    users.iter().try_fold(0u64, |total, user| {
+        total
+            .checked_add(user.weighted_stake)
+            .ok_or_else(|| ErrorCode::MathOverflow.into())
+    })
+}
+
+/// Move the protocol-wide TVL by the change in one pool's `total_staked`.
+/// Stake deposited before TVL tracking was never counted, so withdrawals
+/// saturate at zero instead of failing.
+fn record_tvl_change(global_state: &mut GlobalState, before: u64, after: u64) {
+    global_state.total_value_locked = if after >= before {
+        global_state.total_value_locked.saturating_add((after - before) as u128)
+    } else {
+        global_state.total_value_locked.saturating_sub((before - after) as u128)
+    };
+}
+
+/// Add claimed primary rewards to the position's lifetime total and announce the claim
+fn record_claim(pool: Pubkey, user_key: Pubkey, user: &mut User, amount: u64) -> Result<()> {
+    user.lifetime_rewards_claimed = user.lifetime_rewards_claimed
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    emit!(ClaimEvent {
+        pool,
+        user: user_key,
+        amount,
+        lifetime_rewards_claimed: user.lifetime_rewards_claimed,
+    });
+    Ok(())
+}
+
+/// Add primary rewards paid out of a pool to the protocol-wide total
+fn record_rewards_distributed(global_state: &mut GlobalState, amount: u64) {
+    global_state.total_rewards_distributed = global_state.total_rewards_distributed
+        .saturating_add(amount as u128);
+}
+
+/// Bring `pool` and `user` up to `now` the way every payout does, renewing an
+/// auto-renewing lock first, and return the gross primary rewards owed
+fn accrue_user_rewards(pool: &mut Pool, user: &mut User, now: i64) -> Result<u64> {
+    renew_lock(user, now)?;
+    update_pool_rewards(pool, now)?;
+    pending_rewards(pool, user)?
+        .checked_add(user.unclaimed_rewards)
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Split a payout of `gross` primary rewards into the pool's performance fee
+/// and the net owed to the position. Every path that pays out or restakes
+/// primary rewards settles through here.
+fn settle_rewards(pool: &Pool, gross: u64) -> Result<RewardSettlement> {
+    let performance_fee = (gross as u128)
+        .checked_mul(pool.performance_fee_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(BPS_SCALE as u128)
+        .ok_or(ErrorCode::MathOverflow)? as u64;
+    Ok(RewardSettlement {
+        gross,
+        performance_fee,
+        net: gross - performance_fee,
+    })
+}
+
+/// Underlying per LST scaled by `SHARE_PRECISION` (1:1 while no LST exists)
+fn exchange_rate(pool: &Pool, lst_supply: u64) -> Result<u128> {
+    if lst_supply == 0 {
+        return Ok(SHARE_PRECISION);
+    }
+    (pool.total_staked as u128)
+        .checked_mul(SHARE_PRECISION)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(lst_supply as u128)
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// LST to mint for a deposit of `amount` at the pool's exchange rate
+fn lst_for_deposit(pool: &Pool, lst_supply: u64, amount: u64) -> Result<u64> {
+    if lst_supply == 0 || pool.total_staked == 0 {
+        return Ok(amount);
+    }
+    let shares = (amount as u128)
+        .checked_mul(lst_supply as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(pool.total_staked as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    u64::try_from(shares).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// LST worth `amount` at the pool's exchange rate, rounded up. Used when stake
+/// changes hands inside the pool, so the holder giving it up covers the dust.
+fn lst_for_amount_rounded_up(pool: &Pool, lst_supply: u64, amount: u64) -> Result<u64> {
+    if lst_supply == 0 || pool.total_staked == 0 {
+        return Ok(amount);
+    }
+    let total_staked = pool.total_staked as u128;
+    let shares = (amount as u128)
+        .checked_mul(lst_supply as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_add(total_staked - 1)
+        .ok_or(ErrorCode::MathOverflow)?
+        / total_staked;
+    u64::try_from(shares).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// Underlying tokens owed for `lst_amount` at the pool's exchange rate. The
+/// last redeemer takes the whole vault so no rounding dust is left behind.
+fn redeemable_amount(pool: &Pool, lst_supply: u64, vault_amount: u64, lst_amount: u64) -> Result<u64> {
+    if lst_amount == lst_supply {
+        return Ok(vault_amount);
+    }
+    let amount = (lst_amount as u128)
+        .checked_mul(pool.total_staked as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(lst_supply as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    u64::try_from(amount).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// Principal of `user`'s position backed by `lst_amount` of the LST issued to
+/// it. Rounded up so a partial exit never leaves the position over-credited;
+/// redeeming all of the position's LST releases all of its principal.
+fn principal_for_lst(user: &User, lst_amount: u64) -> Result<u64> {
+    require!(lst_amount <= user.lst_minted, ErrorCode::InsufficientStake);
+    if lst_amount == user.lst_minted {
+        return Ok(user.amount);
+    }
+    let lst_minted = user.lst_minted as u128;
+    let principal = (user.amount as u128)
+        .checked_mul(lst_amount as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_add(lst_minted - 1)
+        .ok_or(ErrorCode::MathOverflow)?
+        / lst_minted;
+    u64::try_from(principal).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+// ============ Stake Helpers ============
+
+/// Set up a fresh position for `owner` and count it against the pool's user cap
+fn init_user(
+    pool: &mut Account<'_, Pool>,
+    user: &mut Account<'_, User>,
+    owner: Pubkey,
+    referrer: Pubkey,
+    label: [u8; 16],
+    position_index: u8,
+    bump: u8,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    require_keys_neq!(referrer, owner, ErrorCode::InvalidReferrer);
+
+    // Enforce the participant cap (0 = unlimited)
+    require!(
+        pool.max_users == 0 || pool.user_count < pool.max_users,
+        ErrorCode::MaxUsersReached
+    );
+    pool.user_count = pool.user_count.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+    user.bump = bump;
+    user.amount = 0;
+    user.lock_type = 0;
+    user.lock_start_timestamp = 0;
+    user.lock_end_timestamp = 0;
+    user.bonus_multiplier = BPS_SCALE as u16;
+    user.last_reward_claim_timestamp = clock.unix_timestamp;
+    user.reward_debt = 0;
+    user.unclaimed_rewards = 0;
+    user.version = USER_VERSION;
+    user.pool = pool.key();
+    user.owner = owner;
+    user.referrer = referrer;
+    user.weighted_stake = 0;
+    user.label = label;
+    user.claim_delegate = None;
+    user.position_index = position_index;
+    user.lst_minted = 0;
+
+    msg!("User account created for pool: {}", String::from_utf8_lossy(&pool.pool_id));
+    Ok(())
+}
+
+/// Extra user PDA seed for `position_index`. Index 0 adds no bytes, so every
+/// wallet's original position keeps its pre-index address.
+fn position_seed(position_index: &u8) -> &[u8] {
+    if *position_index == 0 {
+        &[]
+    } else {
+        std::slice::from_ref(position_index)
+    }
+}
+
+/// Share of `amount` withheld by an early (in-lock) withdrawal, rounded up
+/// so the net payout rounds down
+fn early_withdraw_penalty(pool: &Pool, amount: u64) -> Result<u64> {
+    let penalty = (amount as u128)
+        .checked_mul(pool.early_withdraw_penalty_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .div_ceil(BPS_SCALE as u128);
+    u64::try_from(penalty).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// Payout of redeeming `lst_amount` at `now` against an already settled pool
+fn unstake_preview(
+    pool: &Pool,
+    user: &User,
+    lst_supply: u64,
+    vault_balance: u64,
+    lst_amount: u64,
+    now: i64,
+) -> Result<UnstakePreview> {
+    let amount = redeemable_amount(pool, lst_supply, vault_balance, lst_amount)?;
+
+    // Only an emergency exit is possible inside the lock
+    if now < user.lock_end_timestamp {
+        let penalty = early_withdraw_penalty(pool, amount)?;
+        return Ok(UnstakePreview {
+            principal_returned: amount.checked_sub(penalty).ok_or(ErrorCode::MathOverflow)?,
+            penalty,
+            pending_rewards: 0,
+        });
+    }
+
+    // Rewards are paid like a claim, net of the performance fee
+    let gross = pending_rewards(pool, user)?
+        .checked_add(user.unclaimed_rewards)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(UnstakePreview {
+        principal_returned: amount,
+        penalty: 0,
+        pending_rewards: settle_rewards(pool, gross)?.net,
+    })
+}
+
+/// Accounts a reward claim reads and pays out through, shared by
+/// `claim_rewards` and each entry of `claim_rewards_batch`
+struct ClaimAccounts<'a, 'info> {
+    pool: &'a mut Account<'info, Pool>,
+    user: &'a mut Account<'info, User>,
+    authority: Pubkey, // Signer: the owner or the position's claim delegate
+    reward_mint: &'a InterfaceAccount<'info, Mint>,
+    reward_vault: &'a InterfaceAccount<'info, TokenAccount>,
+    user_reward_account: AccountInfo<'info>,
+    referrer_reward_account: Option<AccountInfo<'info>>,
+    fee_recipient_reward_account: Option<AccountInfo<'info>>,
+    stream_accounts: &'info [AccountInfo<'info>],
+    token_program: &'a Interface<'info, TokenInterface>,
+}
+
+/// Pay out `user`'s rewards: `amount` of the primary reward (`None` claims what
+/// the vault can cover) and every extra stream in full. Returns the primary
+/// rewards distributed, referral bonus included.
+fn process_claim(accounts: ClaimAccounts, amount: Option<u64>, now: i64) -> Result<u64> {
+    let ClaimAccounts {
+        pool,
+        user,
+        authority,
+        reward_mint,
+        reward_vault,
+        user_reward_account,
+        referrer_reward_account,
+        fee_recipient_reward_account,
+        stream_accounts,
+        token_program,
+    } = accounts;
+    // Throttle claims; rewards keep accruing in the meantime
+    let since_last_claim = elapsed_since(now, user.last_reward_claim_timestamp);
+    require!(
+        since_last_claim >= pool.min_claim_interval,
+        ErrorCode::ClaimTooSoon
+    );
+
+    // Rewards accrued since the last settlement plus any banked rewards
+    let available = accrue_user_rewards(pool, user, now)?;
+    settle_reward_streams(pool, user)?;
+
+    let stream_count = pool.reward_stream_count as usize;
+    let stream_rewards: Vec<u64> = user.reward_streams[..stream_count]
+        .iter()
+        .map(|position| position.unclaimed_rewards)
+        .collect();
+
+    require!(
+        available > 0 || stream_rewards.iter().any(|amount| *amount > 0),
+        ErrorCode::NoRewardsAvailable
+    );
+
+    // The referral bonus is paid on top of the claim, not out of it
+    let referral_bps = if user.referrer != Pubkey::default() {
+        pool.referral_bonus_bps as u128
+    } else {
+        0
+    };
+
+    let rewards = match amount {
+        Some(requested) => {
+            require!(requested > 0, ErrorCode::InvalidAmount);
+            require!(requested <= available, ErrorCode::InsufficientRewards);
+            requested
+        }
+        // Claiming everything pays what the vault can cover (bonus
+        // included); the shortfall stays banked for a later claim
+        None => {
+            let affordable = (reward_vault.amount as u128)
+                .checked_mul(BPS_SCALE as u128)
+                .ok_or(ErrorCode::MathOverflow)?
+                / (BPS_SCALE as u128 + referral_bps);
+            available.min(u64::try_from(affordable).unwrap_or(u64::MAX))
+        }
+    };
+    require!(
+        rewards > 0 || available == 0 || stream_rewards.iter().any(|amount| *amount > 0),
+        ErrorCode::InsufficientRewardFunds
+    );
+
+    let referral_bonus = (rewards as u128)
+        .checked_mul(referral_bps)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(BPS_SCALE as u128)
+        .ok_or(ErrorCode::MathOverflow)? as u64;
+
+    // The performance fee comes out of the claim; the user is paid the net
+    let settlement = settle_rewards(pool, rewards)?;
+
+    require!(
+        reward_vault.amount
+            >= rewards.checked_add(referral_bonus).ok_or(ErrorCode::MathOverflow)?,
+        ErrorCode::InsufficientRewardFunds
+    );
+
+    // Update last claim timestamp and reset the reward debt; anything not
+    // claimed stays banked
+    user.last_reward_claim_timestamp = now;
+    user.reward_debt = reward_debt_for(pool, user.amount)?;
+    user.unclaimed_rewards = available - rewards;
+    if amount.is_none() && user.unclaimed_rewards > 0 {
+        msg!("Reward vault short; {} rewards left banked", user.unclaimed_rewards);
+    }
+    for position in user.reward_streams.iter_mut() {
+        position.unclaimed_rewards = 0;
+    }
+    sync_reward_stream_debts(pool, user)?;
+    reweigh_stake(pool, user, now)?;
+
+    // Flag a vault that has dropped below the operator's threshold,
+    // emitting only when the flag first trips
+    let vault_remaining = reward_vault.amount - rewards - referral_bonus;
+    let low_balance = vault_remaining < pool.low_balance_threshold;
+    if low_balance && !pool.low_reward_balance {
+        emit!(LowRewardBalanceEvent {
+            pool: pool.key(),
+            vault_balance: vault_remaining,
+            threshold: pool.low_balance_threshold,
+        });
+    }
+    pool.low_reward_balance = low_balance;
+
+    // Pay rewards out of the reward vault
+    if settlement.net > 0 {
+        record_claim(pool.key(), user.key(), user, settlement.net)?;
+        transfer_from_pool(
+            pool,
+            reward_vault,
+            user_reward_account,
+            reward_mint,
+            token_program,
+            settlement.net,
+        )?;
+    }
+    pay_performance_fee(
+        pool,
+        reward_vault,
+        fee_recipient_reward_account,
+        reward_mint,
+        token_program,
+        settlement.performance_fee,
+    )?;
+
+    if referral_bonus > 0 {
+        let referrer_account = referrer_reward_account.ok_or(ErrorCode::InvalidReferrer)?;
+        transfer_from_pool(
+            pool,
+            reward_vault,
+            referrer_account,
+            reward_mint,
+            token_program,
+            referral_bonus,
+        )?;
+        msg!("Paid {} tokens of referral bonus", referral_bonus);
+    }
+
+    // Pay each extra stream out of its own vault
+    require!(
+        stream_accounts.len() == stream_count * 3,
+        ErrorCode::InvalidRewardStream
+    );
+    for (index, accounts) in stream_accounts.chunks(3).enumerate() {
+        let stream = pool.reward_streams[index];
+        let amount = stream_rewards[index];
+
+        let stream_vault = InterfaceAccount::<TokenAccount>::try_from(&accounts[0])?;
+        let stream_mint = InterfaceAccount::<Mint>::try_from(&accounts[1])?;
+        let user_token_account = InterfaceAccount::<TokenAccount>::try_from(&accounts[2])?;
+
+        require_keys_eq!(stream_vault.key(), stream.reward_vault, ErrorCode::InvalidRewardStream);
+        require_keys_eq!(stream_mint.key(), stream.reward_mint, ErrorCode::InvalidMint);
+        require_keys_eq!(user_token_account.mint, stream.reward_mint, ErrorCode::InvalidMint);
+        require!(
+            user.owner == authority || user_token_account.owner == user.owner,
+            ErrorCode::Unauthorized
+        );
+
+        if amount == 0 {
+            continue;
+        }
+        require!(stream_vault.amount >= amount, ErrorCode::InsufficientRewardFunds);
+
+        transfer_from_pool(
+            pool,
+            &stream_vault,
+            accounts[2].clone(),
+            &stream_mint,
+            token_program,
+            amount,
+        )?;
+        msg!("Claimed {} tokens from reward stream {}", amount, index);
+    }
+
+    msg!("Claimed {} tokens in rewards", rewards);
+    Ok(rewards + referral_bonus)
+}
+
+/// Accounts shared by `stake`, `stake_init` and `stake_for`
+struct DepositAccounts<'a, 'info> {
+    pool: &'a mut Account<'info, Pool>,
+    user: &'a mut Account<'info, User>,
+    stake_mint: &'a InterfaceAccount<'info, Mint>,
+    pool_vault: &'a mut InterfaceAccount<'info, TokenAccount>,
+    source: &'a InterfaceAccount<'info, TokenAccount>,
+    lst_mint: &'a InterfaceAccount<'info, Mint>,
+    lst_destination: &'a InterfaceAccount<'info, TokenAccount>,
+    treasury: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    funder: &'a Signer<'info>,
+    token_program: &'a Interface<'info, TokenInterface>,
+}
+
+/// Deposit `amount` from `source` into `user`'s position in the given lock tier.
+/// The position is credited with what the vault actually receives, so
+/// Token-2022 transfer fees are borne by the depositor.
+fn process_stake(accounts: DepositAccounts, amount: u64, tier_index: u8, auto_renew: bool) -> Result<()> {
+    require!(amount > 0, ErrorCode::InvalidAmount);
+
+    let DepositAccounts {
+        pool,
+        user,
+        stake_mint,
+        pool_vault,
+        source,
+        lst_mint,
+        lst_destination,
+        treasury,
+        funder,
+        token_program,
+    } = accounts;
+    let clock = Clock::get()?;
+
+    // Reject unknown tiers before moving any tokens
+    pool.lock_tier(tier_index)?;
+
+    // Split off the deposit fee; only the net amount is staked
+    let fee = (amount as u128)
+        .checked_mul(pool.deposit_fee_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(BPS_SCALE as u128)
+        .ok_or(ErrorCode::MathOverflow)? as u64;
+    let net_amount = amount.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
+    require!(net_amount > 0, ErrorCode::StakeTooSmallAfterFee);
+
+    // Enforce pool deposit limits (a zero cap means uncapped)
+    require!(amount >= pool.min_stake, ErrorCode::BelowMinimumStake);
+    if pool.max_total_staked > 0 {
+        let new_total = pool.total_staked
+            .checked_add(net_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(new_total <= pool.max_total_staked, ErrorCode::PoolCapExceeded);
+    }
+
+    // Move the staked tokens into the pool vault. Native SOL pools stake
+    // wrapped SOL, so every pool goes through the same SPL path.
+    let vault_before = pool_vault.amount;
+    let transfer_accounts = TransferChecked {
+        from: source.to_account_info(),
+        to: pool_vault.to_account_info(),
+        authority: funder.to_account_info(),
+        mint: stake_mint.to_account_info(),
+    };
+
+    let transfer_ctx = CpiContext::new(
+        token_program.to_account_info(),
+        transfer_accounts,
+    );
+
+    let decimals = stake_mint.decimals;
+    token_interface::transfer_checked(transfer_ctx, net_amount, decimals)?;
+
+    pool_vault.reload()?;
+    let received = pool_vault.amount
+        .checked_sub(vault_before)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(received > 0, ErrorCode::StakeTooSmallAfterFee);
+
+    msg!("Transferred {} tokens to pool vault ({} received)", net_amount, received);
+
+    let lst_to_mint = credit_stake(
+        pool,
+        user,
+        received,
+        lst_mint.supply,
+        tier_index,
+        auto_renew,
+        clock.unix_timestamp,
+    )?;
+
+    // Route the fee to the pool treasury
+    if fee > 0 {
+        let treasury = treasury.ok_or(ErrorCode::InvalidTreasury)?;
+
+        let fee_accounts = TransferChecked {
+            from: source.to_account_info(),
+            to: treasury.to_account_info(),
+            authority: funder.to_account_info(),
+            mint: stake_mint.to_account_info(),
+        };
+
+        let fee_ctx = CpiContext::new(
+            token_program.to_account_info(),
+            fee_accounts,
+        );
+
+        token_interface::transfer_checked(fee_ctx, fee, decimals)?;
+        msg!("Transferred {} tokens to treasury as deposit fee", fee);
+    }
+
+    // Mint the LST receipt to the position owner
+    mint_lst(
+        pool,
+        lst_mint,
+        lst_destination.to_account_info(),
+        token_program,
+        lst_to_mint,
+    )?;
+
+    emit!(StakeEvent {
+        pool: pool.key(),
+        user: user.key(),
+        amount: received,
+        lst_minted: lst_to_mint,
+        lock_type: user.lock_type,
+        label: user.label,
+    });
+
+    msg!("Minted {} LST", lst_to_mint);
+    msg!("Staked {} tokens with lock tier: {}", received, tier_index);
+    Ok(())
+}
+
+/// Credit `received` tokens, already in the pool vault, to `user`'s position
+/// and return the LST to mint for them. Settles the position first.
+fn credit_stake(
+    pool: &mut Pool,
+    user: &mut User,
+    received: u64,
+    lst_supply: u64,
+    tier_index: u8,
+    auto_renew: bool,
+    now: i64,
+) -> Result<u64> {
+    // Bring the pool accumulator up to date
+    update_pool_rewards(pool, now)?;
+
+    // Bank rewards earned on the existing balance before it changes
+    let pending = pending_rewards(pool, user)?;
+    user.unclaimed_rewards = user.unclaimed_rewards
+        .checked_add(pending)
+        .ok_or(ErrorCode::MathOverflow)?;
+    settle_reward_streams(pool, user)?;
+    renew_lock(user, now)?;
+
+    // Only a fresh position picks its lock tier. Top-ups join the existing
+    // position: they inherit its multiplier and remaining lock term, and
+    // `tier_index` is ignored.
+    let is_new_user = user.amount == 0;
+
+    user.amount = user.amount.checked_add(received).ok_or(ErrorCode::MathOverflow)?;
+
+    if is_new_user {
+        let tier = pool.lock_tier(tier_index)?;
+        user.lock_type = tier_index;
+
+        // Flexible stakes record a start time too; it drives the loyalty bonus
+        user.lock_start_timestamp = now;
+        user.lock_end_timestamp = tier.lock_end(now)?;
+        user.bonus_multiplier = tier.multiplier()?;
+        // Flexible positions have no term to renew
+        user.auto_renew = auto_renew && user.lock_end_timestamp > 0;
+    }
+
+    user.last_reward_claim_timestamp = now;
+    user.reward_debt = reward_debt_for(pool, user.amount)?;
+    sync_reward_stream_debts(pool, user)?;
+    reweigh_stake(pool, user, now)?;
+
+    // LST shares at the current exchange rate (1:1 for the first deposit)
+    let lst_to_mint = lst_for_deposit(pool, lst_supply, received)?;
+    require!(lst_to_mint > 0, ErrorCode::InvalidAmount);
+    user.lst_minted = user.lst_minted
+        .checked_add(lst_to_mint)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // Update pool totals
+    pool.total_staked = pool.total_staked
+        .checked_add(received)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(lst_to_mint)
+}
+
+/// Governance weight of a position: stake scaled by its lock multiplier
+fn voting_weight(user: &User) -> Result<u64> {
+    let weight = (user.amount as u128)
+        .checked_mul(user.bonus_multiplier as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / BPS_SCALE as u128;
+    u64::try_from(weight).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// Most recent entry in the user's snapshot ring buffer
+fn latest_snapshot(user: &User) -> Option<&StakeSnapshot> {
+    let latest = user.snapshot_count.checked_sub(1)?;
+    Some(&user.stake_snapshots[(latest % MAX_STAKE_SNAPSHOTS as u64) as usize])
+}
+
+/// Lock countdown for `user` at `now` (flexible positions are never locked)
+fn unlock_info(user: &User, now: i64) -> UnlockInfo {
+    let seconds_remaining = elapsed_since(user.lock_end_timestamp, now);
+    UnlockInfo {
+        is_locked: seconds_remaining > 0,
+        seconds_remaining,
+        unlock_timestamp: user.lock_end_timestamp,
+    }
+}
+
+/// Roll a lapsed auto-renewing lock forward by whole terms so it covers `now`.
+/// The multiplier is untouched, so the bonus carries into the new term.
+fn renew_lock(user: &mut User, now: i64) -> Result<()> {
+    if !user.auto_renew || user.lock_end_timestamp == 0 || now < user.lock_end_timestamp {
+        return Ok(());
+    }
+    let duration = user.lock_end_timestamp
+        .checked_sub(user.lock_start_timestamp)
+        .filter(|duration| *duration > 0)
+        .ok_or(ErrorCode::InvalidLockDuration)?;
+    // The elapsed span fits in an i64, so the cast back is lossless
+    let terms = elapsed_since(now, user.lock_end_timestamp) as i64 / duration + 1;
+    let shift = terms.checked_mul(duration).ok_or(ErrorCode::MathOverflow)?;
+    user.lock_start_timestamp = user.lock_start_timestamp
+        .checked_add(shift)
+        .ok_or(ErrorCode::MathOverflow)?;
+    user.lock_end_timestamp = user.lock_end_timestamp
+        .checked_add(shift)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(())
+}
+
+// ============ Token Helpers ============
+
+/// Transfer tokens out of a pool-owned vault, signed by the pool PDA
+fn transfer_from_pool<'info>(
+    pool: &Account<'info, Pool>,
+    from: &InterfaceAccount<'info, TokenAccount>,
+    to: AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<()> {
+    let bump = [pool.bump];
+    let signer_seeds: &[&[&[u8]]] = &[&[b"pool", pool.pool_id.as_ref(), &bump]];
+
+    let transfer_accounts = TransferChecked {
+        from: from.to_account_info(),
+        to,
+        authority: pool.to_account_info(),
+        mint: mint.to_account_info(),
+    };
+
+    let transfer_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        transfer_accounts,
+        signer_seeds,
+    );
+
+    token_interface::transfer_checked(transfer_ctx, amount, mint.decimals)
+}
+
+/// Mint LST receipts, signed by the pool PDA as mint authority
+fn mint_lst<'info>(
+    pool: &Account<'info, Pool>,
+    lst_mint: &InterfaceAccount<'info, Mint>,
+    to: AccountInfo<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<()> {
+    let bump = [pool.bump];
+    let signer_seeds: &[&[&[u8]]] = &[&[b"pool", pool.pool_id.as_ref(), &bump]];
+
+    let mint_accounts = MintTo {
+        mint: lst_mint.to_account_info(),
+        to,
+        authority: pool.to_account_info(),
+    };
+
+    let mint_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        mint_accounts,
+        signer_seeds,
+    );
+
+    token_interface::mint_to(mint_ctx, amount)
+}
+
+/// Burn LST receipts from the holder's account
+fn burn_lst<'info>(
+    lst_mint: &InterfaceAccount<'info, Mint>,
+    from: &InterfaceAccount<'info, TokenAccount>,
+    authority: &Signer<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<()> {
+    let burn_accounts = Burn {
+        mint: lst_mint.to_account_info(),
+        from: from.to_account_info(),
+        authority: authority.to_account_info(),
+    };
+
+    let burn_ctx = CpiContext::new(token_program.to_account_info(), burn_accounts);
+
+    token_interface::burn(burn_ctx, amount)
+}
+
+/// Pay the performance fee out of a pool vault to the fee recipient's account
+fn pay_performance_fee<'info>(
+    pool: &Account<'info, Pool>,
+    vault: &InterfaceAccount<'info, TokenAccount>,
+    fee_recipient_account: Option<AccountInfo<'info>>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    fee: u64,
+) -> Result<()> {
+    if fee == 0 {
+        return Ok(());
+    }
+    let fee_account = fee_recipient_account.ok_or(ErrorCode::InvalidFeeRecipient)?;
+    transfer_from_pool(pool, vault, fee_account, mint, token_program, fee)?;
+    msg!("Paid {} tokens of performance fee", fee);
+    Ok(())
+}
+
+/// Close an empty pool-owned vault, signed by the pool PDA
+fn close_pool_vault<'info>(
+    pool: &Account<'info, Pool>,
+    vault: &InterfaceAccount<'info, TokenAccount>,
+    destination: AccountInfo<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+) -> Result<()> {
+    let bump = [pool.bump];
+    let signer_seeds: &[&[&[u8]]] = &[&[b"pool", pool.pool_id.as_ref(), &bump]];
+
+    let close_accounts = CloseAccount {
+        account: vault.to_account_info(),
+        destination,
+        authority: pool.to_account_info(),
+    };
+
+    let close_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        close_accounts,
+        signer_seeds,
+    );
+
+    token_interface::close_account(close_ctx)
+}
+
+// ============ Account Structures ============
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    // `init_if_needed` so a repeat call reaches the handler and fails with
+    // `AlreadyInitialized` instead of an opaque account-in-use error
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + GlobalState::INIT_SPACE,
+        seeds = [b"global"],
+        bump
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    pub new_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaxPools<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetEmergencyShutdown<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(referrer: Option<Pubkey>, label: Option<[u8; 16]>, position_index: u8)]
+pub struct CreateUserAccount<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + User::INIT_SPACE,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&position_index)],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(position_index: u8)]
+pub struct MigrateUser<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// CHECK: Possibly legacy-sized user account; deserialized manually after realloc
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&position_index)],
+        bump,
+        owner = crate::ID,
+        realloc = 8 + User::INIT_SPACE,
+        realloc::payer = payer,
+        realloc::zero = true,
+    )]
+    pub user: AccountInfo<'info>,
+
+    /// Liquid staking token mint (prices the LST credited to legacy positions)
+    #[account(address = pool.lst_mint @ ErrorCode::InvalidMint)]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: [u8; 32])]
+pub struct CreatePool<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump,
+        constraint = !global_state.emergency_shutdown @ ErrorCode::ProtocolShutdown
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    // `init_if_needed` so a reused `pool_id` fails with `PoolAlreadyExists`
+    // instead of an opaque account-in-use error. The check runs before the
+    // vaults below are touched.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + Pool::INIT_SPACE,
+        seeds = [b"pool", pool_id.as_ref()],
+        bump,
+        constraint = pool.authority == Pubkey::default() @ ErrorCode::PoolAlreadyExists
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// Mint of the token being staked
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// Vault holding all staked tokens, owned by the pool PDA
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [b"vault", pool_id.as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = pool,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// Liquid staking token mint; the pool PDA must be its mint authority and
+    /// nothing may have been minted yet
+    #[account(
+        mint::authority = pool,
+        constraint = lst_mint.supply == 0 @ ErrorCode::InvalidMint,
+    )]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// Mint of the reward token
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    /// Vault holding reward tokens paid out on claim, owned by the pool PDA
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [b"reward_vault", pool_id.as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = pool,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AddRewardStream<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// Mint of the extra reward token
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    /// Vault holding this stream's reward tokens, owned by the pool PDA
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"reward_vault", pool.pool_id.as_ref(), reward_mint.key().as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = pool,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, tier_index: u8)]
+pub struct Stake<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump,
+        constraint = !global_state.emergency_shutdown @ ErrorCode::ProtocolShutdown
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    /// Mint account for the stake token
+    #[account(address = pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// Pool vault (receives staked tokens)
+    #[account(
+        mut,
+        seeds = [b"vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = pool,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// User's token account the stake is pulled from
+    #[account(
+        mut,
+        constraint = stake_token_account.mint == pool.stake_mint @ ErrorCode::InvalidMint,
+        token::authority = authority,
+    )]
+    pub stake_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Liquid staking token mint (mint authority is the pool PDA)
+    #[account(
+        mut,
+        address = pool.lst_mint @ ErrorCode::InvalidMint,
+    )]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// User's LST account (receives the staking receipt)
+    #[account(
+        mut,
+        token::mint = lst_mint,
+        token::authority = authority,
+    )]
+    pub user_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Pool treasury (receives the deposit fee; required when the fee is nonzero)
+    #[account(
+        mut,
+        address = pool.treasury @ ErrorCode::InvalidTreasury,
+        token::mint = stake_mint,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Staker's whitelist entry (required while the pool's whitelist is enabled)
+    #[account(
+        seeds = [b"wl", pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump = whitelist.bump
+    )]
+    pub whitelist: Option<Account<'info, Whitelist>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, tier_index: u8)]
+pub struct StakeInit<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump,
+        constraint = !global_state.emergency_shutdown @ ErrorCode::ProtocolShutdown
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    // Created on first use; the handler tells a fresh account from a live one
+    // by its owner field and never re-initializes the latter
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + User::INIT_SPACE,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    /// Mint account for the stake token
+    #[account(address = pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// Pool vault (receives staked tokens)
+    #[account(
+        mut,
+        seeds = [b"vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = pool,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// User's token account the stake is pulled from
+    #[account(
+        mut,
+        constraint = stake_token_account.mint == pool.stake_mint @ ErrorCode::InvalidMint,
+        token::authority = authority,
+    )]
+    pub stake_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Liquid staking token mint (mint authority is the pool PDA)
+    #[account(
+        mut,
+        address = pool.lst_mint @ ErrorCode::InvalidMint,
+    )]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// User's LST account (receives the staking receipt)
+    #[account(
+        mut,
+        token::mint = lst_mint,
+        token::authority = authority,
+    )]
+    pub user_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Pool treasury (receives the deposit fee; required when the fee is nonzero)
+    #[account(
+        mut,
+        address = pool.treasury @ ErrorCode::InvalidTreasury,
+        token::mint = stake_mint,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Staker's whitelist entry (required while the pool's whitelist is enabled)
+    #[account(
+        seeds = [b"wl", pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump = whitelist.bump
+    )]
+    pub whitelist: Option<Account<'info, Whitelist>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(beneficiary: Pubkey)]
+pub struct StakeFor<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump,
+        constraint = !global_state.emergency_shutdown @ ErrorCode::ProtocolShutdown
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), beneficiary.as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    /// Mint account for the stake token
+    #[account(address = pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// Pool vault (receives staked tokens)
+    #[account(
+        mut,
+        seeds = [b"vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = pool,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// Funder's token account the stake is pulled from
+    #[account(
+        mut,
+        constraint = stake_token_account.mint == pool.stake_mint @ ErrorCode::InvalidMint,
+        token::authority = funder,
+    )]
+    pub stake_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Liquid staking token mint (mint authority is the pool PDA)
+    #[account(
+        mut,
+        address = pool.lst_mint @ ErrorCode::InvalidMint,
+    )]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// Beneficiary's LST account (receives the staking receipt)
+    #[account(
+        mut,
+        token::mint = lst_mint,
+        constraint = beneficiary_lst_account.owner == beneficiary @ ErrorCode::Unauthorized,
+    )]
+    pub beneficiary_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Pool treasury (receives the deposit fee; required when the fee is nonzero)
+    #[account(
+        mut,
+        address = pool.treasury @ ErrorCode::InvalidTreasury,
+        token::mint = stake_mint,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Beneficiary's whitelist entry (required while the pool's whitelist is enabled)
+    #[account(
+        seeds = [b"wl", pool.pool_id.as_ref(), beneficiary.as_ref()],
+        bump = whitelist.bump
+    )]
+    pub whitelist: Option<Account<'info, Whitelist>>,
+
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Unstake<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch,
+        constraint = user.owner == authority.key() @ ErrorCode::Unauthorized
+    )]
+    pub user: Account<'info, User>,
+
+    /// Mint account for the stake token
+    #[account(address = pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// Pool vault (holds staked tokens)
+    #[account(
+        mut,
+        seeds = [b"vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = pool,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// User's token account (receives unstaked tokens)
+    #[account(
+        mut,
+        constraint = stake_token_account.mint == pool.stake_mint @ ErrorCode::InvalidMint,
+        token::authority = authority,
+    )]
+    pub stake_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Liquid staking token mint
+    #[account(
+        mut,
+        address = pool.lst_mint @ ErrorCode::InvalidMint,
+    )]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// User's LST account (receipt burned on redemption)
+    #[account(
+        mut,
+        token::mint = lst_mint,
+        token::authority = authority,
+    )]
+    pub user_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CompleteUnstake<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    /// Mint account for the stake token
+    #[account(address = pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// Pool vault (holds queued unstakes)
+    #[account(
+        mut,
+        seeds = [b"vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = pool,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// User's token account (receives the withdrawn tokens)
+    #[account(
+        mut,
+        constraint = stake_token_account.mint == pool.stake_mint @ ErrorCode::InvalidMint,
+        token::authority = authority,
+    )]
+    pub stake_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CancelUnstakeRequest<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump,
+        constraint = !global_state.emergency_shutdown @ ErrorCode::ProtocolShutdown
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    /// Liquid staking token mint
+    #[account(
+        mut,
+        address = pool.lst_mint @ ErrorCode::InvalidMint,
+    )]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// User's LST account (receives the re-issued receipt)
+    #[account(
+        mut,
+        token::mint = lst_mint,
+        token::authority = authority,
+    )]
+    pub user_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct Relock<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetAutoRenew<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetLabel<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetClaimDelegate<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExpireLock<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), owner.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    /// CHECK: Wallet that owns the position (only used for PDA derivation)
+    pub owner: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// Signed for by the owner or the position's claim delegate
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), user.owner.as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch,
+        constraint = user.owner == authority.key()
+            || user.claim_delegate == Some(authority.key()) @ ErrorCode::Unauthorized
+    )]
+    pub user: Account<'info, User>,
+
+    /// Mint of the reward token
+    #[account(address = pool.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    /// Reward vault (pays out claimed rewards)
+    #[account(
+        mut,
+        seeds = [b"reward_vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = pool,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// User's reward token account (receives claimed rewards; must belong to
+    /// the owner when a delegate signs)
+    #[account(
+        mut,
+        constraint = user_reward_account.mint == pool.reward_mint @ ErrorCode::InvalidMint,
+        constraint = user.owner == authority.key()
+            || user_reward_account.owner == user.owner @ ErrorCode::Unauthorized,
+    )]
+    pub user_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Referrer's reward token account (required when a referral bonus is due)
+    #[account(
+        mut,
+        token::mint = reward_mint,
+        constraint = referrer_reward_account.owner == user.referrer @ ErrorCode::InvalidReferrer,
+    )]
+    pub referrer_reward_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Fee recipient's reward token account (required when a performance fee is due)
+    #[account(
+        mut,
+        token::mint = reward_mint,
+        constraint = fee_recipient_reward_account.owner == pool.fee_recipient @ ErrorCode::InvalidFeeRecipient,
+    )]
+    pub fee_recipient_reward_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewardsBatch<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct FundRewardVault<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// Mint of the reward token
+    #[account(address = pool.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    /// Reward vault (receives the funding)
+    #[account(
+        mut,
+        seeds = [b"reward_vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = pool,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// Funder's reward token account
+    #[account(
+        mut,
+        constraint = funder_token_account.mint == pool.reward_mint @ ErrorCode::InvalidMint,
+        token::authority = funder,
+    )]
+    pub funder_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub funder: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ExtendRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// Mint of the reward token
+    #[account(address = pool.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    /// Reward vault (receives the funding)
+    #[account(
+        mut,
+        seeds = [b"reward_vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = pool,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// Authority's reward token account
+    #[account(
+        mut,
+        token::mint = reward_mint,
+        token::authority = authority,
+    )]
+    pub authority_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawRewardSurplus<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// Mint of the reward token
+    #[account(address = pool.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    /// Reward vault (source of the surplus)
+    #[account(
+        mut,
+        seeds = [b"reward_vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = pool,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// Authority's reward token account (receives the surplus)
+    #[account(
+        mut,
+        token::mint = reward_mint,
+    )]
+    pub authority_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SweepDust<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// Mint account for the stake token
+    #[account(address = pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// Vault holding the residue
+    #[account(
+        mut,
+        seeds = [b"vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = pool,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// LST mint of the pool (supply must be zero)
+    #[account(address = pool.lst_mint @ ErrorCode::InvalidMint)]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// Pool treasury (receives the dust)
+    #[account(
+        mut,
+        address = pool.treasury @ ErrorCode::InvalidTreasury,
+        token::mint = stake_mint,
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RescueTokens<'info> {
+    #[account(
+        seeds = [b"global"],
+        bump = global_state.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// Mint of the stray tokens
+    pub rescue_mint: InterfaceAccount<'info, Mint>,
+
+    /// Pool-owned account holding the stray tokens
+    #[account(
+        mut,
+        token::mint = rescue_mint,
+        token::authority = pool,
+    )]
+    pub source: InterfaceAccount<'info, TokenAccount>,
+
+    /// Recovery destination
+    #[account(
+        mut,
+        token::mint = rescue_mint,
+    )]
+    pub recovery_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct Compound<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump,
+        constraint = !global_state.emergency_shutdown @ ErrorCode::ProtocolShutdown
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        constraint = pool.reward_mint == pool.stake_mint @ ErrorCode::CompoundMintMismatch
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    /// Mint account for the stake token (also the reward token)
+    #[account(address = pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// Pool vault (receives the compounded rewards)
+    #[account(
+        mut,
+        seeds = [b"vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = pool,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// Reward vault (source of the compounded rewards)
+    #[account(
+        mut,
+        seeds = [b"reward_vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = pool,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// Liquid staking token mint
+    #[account(
+        mut,
+        address = pool.lst_mint @ ErrorCode::InvalidMint,
+    )]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// User's LST account (receives the receipt for compounded rewards)
+    #[account(
+        mut,
+        token::mint = lst_mint,
+        token::authority = authority,
+    )]
+    pub user_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Fee recipient's reward token account (required when a performance fee is due)
+    #[account(
+        mut,
+        token::mint = stake_mint,
+        constraint = fee_recipient_reward_account.owner == pool.fee_recipient @ ErrorCode::InvalidFeeRecipient,
+    )]
+    pub fee_recipient_reward_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimAndStake<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump,
+        constraint = !global_state.emergency_shutdown @ ErrorCode::ProtocolShutdown
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", source_pool.pool_id.as_ref()],
+        bump = source_pool.bump,
+        constraint = source_pool.reward_mint == destination_pool.stake_mint @ ErrorCode::CompoundMintMismatch
+    )]
+    pub source_pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", source_pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&source_user.position_index)],
+        bump = source_user.bump,
+        constraint = source_user.pool == source_pool.key() @ ErrorCode::PoolMismatch
+    )]
+    pub source_user: Account<'info, User>,
+
+    /// Source reward mint (the destination's stake mint)
+    #[account(address = source_pool.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    /// Source reward vault (pays the claimed rewards)
+    #[account(
+        mut,
+        seeds = [b"reward_vault", source_pool.pool_id.as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = source_pool,
+    )]
+    pub source_reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", destination_pool.pool_id.as_ref()],
+        bump = destination_pool.bump,
+        constraint = destination_pool.key() != source_pool.key() @ ErrorCode::PoolMismatch
+    )]
+    pub destination_pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", destination_pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&destination_user.position_index)],
+        bump = destination_user.bump,
+        constraint = destination_user.pool == destination_pool.key() @ ErrorCode::PoolMismatch
+    )]
+    pub destination_user: Account<'info, User>,
+
+    /// Destination stake vault (receives the rewards as stake)
+    #[account(
+        mut,
+        seeds = [b"vault", destination_pool.pool_id.as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = destination_pool,
+    )]
+    pub destination_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// Destination liquid staking token mint
+    #[account(
+        mut,
+        address = destination_pool.lst_mint @ ErrorCode::InvalidMint,
+    )]
+    pub destination_lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// User's destination LST account (receives the staking receipt)
+    #[account(
+        mut,
+        token::mint = destination_lst_mint,
+        token::authority = authority,
+    )]
+    pub user_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Whitelist entry in the destination pool (required while its whitelist is enabled)
+    #[account(
+        seeds = [b"wl", destination_pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump = destination_whitelist.bump
+    )]
+    pub destination_whitelist: Option<Account<'info, Whitelist>>,
+
+    /// Source pool fee recipient's reward token account (required when a
+    /// performance fee is due)
+    #[account(
+        mut,
+        token::mint = reward_mint,
+        constraint = fee_recipient_reward_account.owner == source_pool.fee_recipient @ ErrorCode::InvalidFeeRecipient,
+    )]
+    pub fee_recipient_reward_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct GetPendingRewards<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        seeds = [b"user", pool.pool_id.as_ref(), owner.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    /// CHECK: Wallet that owns the position (only used for PDA derivation)
+    pub owner: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SnapshotStake<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetVotingWeight<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        seeds = [b"user", pool.pool_id.as_ref(), owner.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    /// CHECK: Wallet that owns the position (only used for PDA derivation)
+    pub owner: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PreviewUnstake<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        seeds = [b"user", pool.pool_id.as_ref(), owner.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(
+        seeds = [b"vault", pool.pool_id.as_ref()],
+        bump,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = pool.lst_mint @ ErrorCode::InvalidMint)]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: Wallet that owns the position (only used for PDA derivation)
+    pub owner: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetUnlockInfo<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        seeds = [b"user", pool.pool_id.as_ref(), owner.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    /// CHECK: Wallet that owns the position (only used for PDA derivation)
+    pub owner: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ApplyNftBoost<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    /// Signer's token account holding the NFT
+    #[account(
+        constraint = nft_token_account.owner == authority.key() @ ErrorCode::NotNftHolder,
+        constraint = nft_token_account.amount == 1 @ ErrorCode::NotNftHolder,
+    )]
+    pub nft_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Metaplex metadata of the held NFT
+    #[account(
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), nft_token_account.mint.as_ref()],
+        seeds::program = token_metadata_program.key(),
+        bump,
+    )]
+    pub nft_metadata: Account<'info, MetadataAccount>,
+
+    /// Records which position the NFT is boosting in this pool
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + NftBoostLock::INIT_SPACE,
+        seeds = [b"nft_boost", pool.pool_id.as_ref(), nft_token_account.mint.as_ref()],
+        bump
+    )]
+    pub nft_boost_lock: Account<'info, NftBoostLock>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_metadata_program: Program<'info, Metadata>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GetPoolApr<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+#[derive(Accounts)]
+pub struct GetRewardRunway<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
 
-    pub system_program: Program<'info, System>,
+    #[account(
+        seeds = [b"reward_vault", pool.pool_id.as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
 }
 
 #[derive(Accounts)]
-#[instruction(pool_id: [u8; 32])]
-pub struct CreatePool<'info> {
+pub struct BatchUpdateRewardRate<'info> {
+    #[account(
+        seeds = [b"global"],
+        bump = global_state.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecomputeWeightedStake<'info> {
+    #[account(
+        seeds = [b"global"],
+        bump = global_state.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
     #[account(
         mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetGlobalStats<'info> {
+    #[account(
         seeds = [b"global"],
         bump = global_state.bump
     )]
     pub global_state: Account<'info, GlobalState>,
+}
+
+#[derive(Accounts)]
+pub struct GetExchangeRate<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(address = pool.lst_mint @ ErrorCode::InvalidMint)]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptPoolAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    pub new_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePool<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub pool: Account<'info, Pool>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPerformanceFee<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(address = pool.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    /// Fee recipient's reward token account (required for a non-zero fee)
+    #[account(token::mint = reward_mint)]
+    pub fee_recipient_reward_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(wallet: Pubkey)]
+pub struct AddToWhitelist<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub pool: Account<'info, Pool>,
 
     #[account(
         init,
         payer = payer,
-        space = 8 + Pool::LEN,
-        seeds = [b"pool", pool_id.as_ref()],
+        space = 8 + Whitelist::INIT_SPACE,
+        seeds = [b"wl", pool.pool_id.as_ref(), wallet.as_ref()],
         bump
     )]
-    pub pool: Account<'info, Pool>,
+    pub whitelist: Account<'info, Whitelist>,
 
     #[account(mut)]
     pub payer: Signer<'info>,
 
+    /// May be a governance PDA signing through CPI, so it never funds rent
     pub authority: Signer<'info>,
 
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(amount: u64, lock_type: u8)]
-pub struct Stake<'info> {
+pub struct RemoveFromWhitelist<'info> {
     #[account(
-        mut,
         seeds = [b"pool", pool.pool_id.as_ref()],
-        bump = pool.bump
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
     )]
     pub pool: Account<'info, Pool>,
 
     #[account(
         mut,
-        seeds = [b"user", pool.pool_id.as_ref(), payer.key().as_ref()],
-        bump
+        close = authority,
+        seeds = [b"wl", pool.pool_id.as_ref(), whitelist.wallet.as_ref()],
+        bump = whitelist.bump
     )]
-    pub user: Account<'info, User>,
+    pub whitelist: Account<'info, Whitelist>,
 
-    /// CHECK: Mint account for the stake token
-    pub stake_mint: Account<'info, Mint>,
-
-    /// CHECK: Pool authority account (receives staked tokens/lamports)
     #[account(mut)]
-    pub pool_authority: AccountInfo<'info>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePool<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+        constraint = pool.total_staked == 0 @ ErrorCode::PoolNotEmpty,
+        constraint = pool.user_count == 0 @ ErrorCode::PoolNotEmpty
+    )]
+    pub pool: Account<'info, Pool>,
 
-    /// CHECK: Pool authority's token account (receives staked SPL tokens)
-    /// Optional: Only required for SPL tokens, not native SOL
-    pub pool_authority_token_account: Option<AccountInfo<'info>>,
+    /// Liquid staking token mint (no LST may remain outstanding)
+    #[account(
+        address = pool.lst_mint @ ErrorCode::InvalidMint,
+        constraint = lst_mint.supply == 0 @ ErrorCode::PoolNotEmpty
+    )]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
 
-    /// CHECK: User's token account
-    /// Optional: Only required for SPL tokens, not native SOL
-    pub user_token_account: Option<AccountInfo<'info>>,
+    /// Vault holding staked tokens (must be empty)
+    #[account(
+        mut,
+        seeds = [b"vault", pool.pool_id.as_ref()],
+        bump,
+        constraint = pool_vault.amount == 0 @ ErrorCode::PoolNotEmpty
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
 
-    #[account(mut)]
-    pub payer: Signer<'info>,
+    /// Primary reward vault (must be empty)
+    #[account(
+        mut,
+        seeds = [b"reward_vault", pool.pool_id.as_ref()],
+        bump,
+        constraint = reward_vault.amount == 0 @ ErrorCode::PoolNotEmpty
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
 
-    /// CHECK: Token program or Token-2022 program
-    /// Optional: Only required for SPL tokens, not native SOL
-    pub token_program: Option<AccountInfo<'info>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
 
-    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct Unstake<'info> {
+#[instruction(new_owner: Pubkey, new_position_index: u8)]
+pub struct TransferPosition<'info> {
     #[account(
         mut,
         seeds = [b"pool", pool.pool_id.as_ref()],
@@ -475,39 +5085,57 @@ pub struct Unstake<'info> {
     )]
     pub pool: Account<'info, Pool>,
 
+    /// Current position (closed, rent returned to the old owner)
     #[account(
         mut,
-        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
-        bump = user.bump
+        close = authority,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
     )]
     pub user: Account<'info, User>,
 
-    /// CHECK: Mint account for the stake token
-    pub stake_mint: Account<'info, Mint>,
+    /// Position recreated under the new owner
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + User::INIT_SPACE,
+        seeds = [b"user", pool.pool_id.as_ref(), new_owner.as_ref(), position_seed(&new_position_index)],
+        bump
+    )]
+    pub new_user: Account<'info, User>,
 
-    /// CHECK: Pool authority account (holds staked tokens/lamports)
-    #[account(mut)]
-    pub pool_authority: AccountInfo<'info>,
+    /// LST mint of the pool
+    #[account(address = pool.lst_mint @ ErrorCode::InvalidMint)]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
 
-    /// CHECK: Pool authority's token account (holds staked SPL tokens)
-    /// Optional: Only required for SPL tokens, not native SOL
-    pub pool_authority_token_account: Option<AccountInfo<'info>>,
+    /// Old owner's LST account
+    #[account(
+        mut,
+        token::mint = lst_mint,
+        token::authority = authority,
+    )]
+    pub user_lst_account: InterfaceAccount<'info, TokenAccount>,
 
-    /// CHECK: User's token account (receives unstaked SPL tokens)
-    /// Optional: Only required for SPL tokens, not native SOL
-    pub user_token_account: Option<AccountInfo<'info>>,
+    /// New owner's LST account (receives the receipts)
+    #[account(
+        mut,
+        token::mint = lst_mint,
+        constraint = new_owner_lst_account.owner == new_owner @ ErrorCode::Unauthorized,
+    )]
+    pub new_owner_lst_account: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(mut)]
     pub authority: Signer<'info>,
 
-    /// CHECK: Token program or Token-2022 program
-    /// Optional: Only required for SPL tokens, not native SOL
-    pub token_program: Option<AccountInfo<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
 
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ClaimRewards<'info> {
+#[instruction(new_owner: Pubkey, new_position_index: u8)]
+pub struct SplitPosition<'info> {
     #[account(
         mut,
         seeds = [b"pool", pool.pool_id.as_ref()],
@@ -515,18 +5143,62 @@ pub struct ClaimRewards<'info> {
     )]
     pub pool: Account<'info, Pool>,
 
+    /// Position being split
     #[account(
         mut,
-        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
-        bump = user.bump
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
     )]
     pub user: Account<'info, User>,
 
+    /// Position created for the new owner
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + User::INIT_SPACE,
+        seeds = [b"user", pool.pool_id.as_ref(), new_owner.as_ref(), position_seed(&new_position_index)],
+        bump
+    )]
+    pub new_user: Account<'info, User>,
+
+    /// LST mint of the pool
+    #[account(address = pool.lst_mint @ ErrorCode::InvalidMint)]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// Signer's LST account
+    #[account(
+        mut,
+        token::mint = lst_mint,
+        token::authority = authority,
+    )]
+    pub user_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// New owner's LST account (receives the split-off receipts)
+    #[account(
+        mut,
+        token::mint = lst_mint,
+        constraint = new_owner_lst_account.owner == new_owner @ ErrorCode::Unauthorized,
+    )]
+    pub new_owner_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
     pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct UpdatePool<'info> {
+pub struct CloseUserAccount<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
     #[account(
         mut,
         seeds = [b"pool", pool.pool_id.as_ref()],
@@ -534,97 +5206,471 @@ pub struct UpdatePool<'info> {
     )]
     pub pool: Account<'info, Pool>,
 
+    #[account(
+        mut,
+        close = user_wallet,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch
+    )]
+    pub user: Account<'info, User>,
+
+    /// Mint account for the stake token
+    #[account(address = pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// Pool vault (returns the remaining stake)
+    #[account(
+        mut,
+        seeds = [b"vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = pool,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// User's token account (receives the remaining stake)
+    #[account(
+        mut,
+        constraint = stake_token_account.mint == pool.stake_mint @ ErrorCode::InvalidMint,
+        token::authority = authority,
+    )]
+    pub stake_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Liquid staking token mint
+    #[account(
+        mut,
+        address = pool.lst_mint @ ErrorCode::InvalidMint,
+    )]
+    pub lst_mint: InterfaceAccount<'info, Mint>,
+
+    /// User's LST account (remaining receipt is burned)
+    #[account(
+        mut,
+        token::mint = lst_mint,
+        token::authority = authority,
+    )]
+    pub user_lst_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Mint of the reward token
+    #[account(address = pool.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    /// Reward vault (pays out pending rewards)
+    #[account(
+        mut,
+        seeds = [b"reward_vault", pool.pool_id.as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = pool,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// User's reward token account (receives pending rewards)
+    #[account(
+        mut,
+        constraint = user_reward_account.mint == pool.reward_mint @ ErrorCode::InvalidMint,
+    )]
+    pub user_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Fee recipient's reward token account (required when a performance fee is due)
+    #[account(
+        mut,
+        token::mint = reward_mint,
+        constraint = fee_recipient_reward_account.owner == pool.fee_recipient @ ErrorCode::InvalidFeeRecipient,
+    )]
+    pub fee_recipient_reward_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: User wallet
+    #[account(mut)]
+    pub user_wallet: AccountInfo<'info>,
+
     pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct CloseUserAccount<'info> {
+#[instruction(user_key: Pubkey)]
+pub struct AdminCloseUser<'info> {
     #[account(
         mut,
         seeds = [b"pool", pool.pool_id.as_ref()],
-        bump = pool.bump
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
     )]
     pub pool: Account<'info, Pool>,
 
-    #[account(
-        mut,
-        close = user_wallet,
-        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
-        bump = user.bump
-    )]
-    pub user: Account<'info, User>,
+    #[account(
+        mut,
+        address = user_key,
+        close = owner,
+        seeds = [b"user", pool.pool_id.as_ref(), user.owner.as_ref(), position_seed(&user.position_index)],
+        bump = user.bump,
+        has_one = pool @ ErrorCode::PoolMismatch,
+        has_one = owner @ ErrorCode::Unauthorized
+    )]
+    pub user: Account<'info, User>,
+
+    /// CHECK: Owner recorded on the user account; receives the rent
+    #[account(mut)]
+    pub owner: AccountInfo<'info>,
+
+    pub authority: Signer<'info>,
+}
+
+// ============ Data Structures ============
+
+#[account]
+#[derive(InitSpace)]
+pub struct GlobalState {
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub pool_count: u64,
+    pub pending_authority: Pubkey, // Proposed authority awaiting acceptance
+    pub emergency_shutdown: bool,  // Blocks new deposits protocol-wide
+    pub max_pools: u64,            // Ceiling on live pools (pool_count)
+    pub total_value_locked: u128,  // Sum of every pool's total_staked (stake-mint base units)
+    pub total_rewards_distributed: u128, // Primary rewards paid out across all pools
+}
+
+#[account]
+#[derive(Default, InitSpace)]
+pub struct Pool {
+    pub bump: u8,
+    pub pool_id: [u8; 32],          // Pool identifier (e.g., "wave", "wealth")
+    pub stake_mint: Pubkey,         // Token being staked
+    pub lst_mint: Pubkey,           // Liquid Staking Token mint
+    pub reward_mint: Pubkey,        // Reward token mint
+    pub reward_per_second: u64,     // Base reward rate (reward-mint base units)
+    pub lock_tiers: [LockTier; MAX_LOCK_TIERS], // Configured lock tiers
+    pub lock_tier_count: u8,        // Number of active entries in lock_tiers
+    pub total_staked: u64,          // Total tokens staked in pool
+    pub total_weighted_stake: u64,  // Sum of user weights (stake x active lock multiplier); the reward denominator
+    pub total_reward_distributed: u64, // Total primary rewards emitted (saturating; checked against reward_emission_cap)
+    pub last_update_timestamp: i64, // Last time pool was updated
+    pub authority: Pubkey,          // Pool authority
+    pub acc_reward_per_share: u128, // Accumulated rewards per unit of weighted stake (scaled by SHARE_PRECISION)
+    pub total_reward_funded: u64,   // Total reward tokens deposited into the reward vault
+    pub early_withdraw_penalty_bps: u16, // Principal withheld on emergency unstake (bps)
+    pub min_stake: u64,             // Minimum amount per deposit
+    pub max_total_staked: u64,      // Cap on total_staked (0 = uncapped)
+    pub reward_streams: [RewardStream; MAX_REWARD_STREAMS], // Extra reward tokens
+    pub reward_stream_count: u8,    // Number of active entries in reward_streams
+    pub unstake_cooldown: u64,      // Seconds between request_unstake and complete_unstake (0 = instant unstake)
+    pub total_unstake_pending: u64, // Queued unstakes still held in the pool vault
+    pub reward_start_time: i64,     // Emissions begin at this time
+    pub reward_end_time: i64,       // Emissions stop at this time (0 = no end)
+    pub user_count: u64,            // Live user accounts in this pool
+    pub max_users: u64,             // Cap on user_count (0 = unlimited)
+    pub deposit_fee_bps: u16,       // Fee taken from each deposit (bps)
+    pub treasury: Pubkey,           // Token account receiving deposit fees
+    pub loyalty_bonus_per_period_bps: u16, // Flexible-stake bonus earned per loyalty period
+    pub loyalty_period_seconds: u64, // Length of a loyalty period (0 = no loyalty bonus)
+    pub max_loyalty_bonus_bps: u16, // Cap on the accumulated loyalty bonus
+    pub boost_nft_collection: Pubkey, // Verified collection granting the NFT boost (default = disabled)
+    pub nft_boost_bps: u16,         // Multiplier bonus granted by the NFT boost
+    pub nft_boost_duration: u64,    // Seconds an applied NFT boost lasts
+    pub max_reward_share_bps: u16,  // Cap on one position's share of emissions (0 = uncapped)
+    pub referral_bonus_bps: u16,    // Extra emission paid to referrers on claims
+    pub reward_curve: [RewardBreakpoint; MAX_REWARD_BREAKPOINTS], // TVL-dependent reward rates
+    pub reward_curve_len: u8,       // Number of configured breakpoints
+    pub min_claim_interval: u64,    // Seconds required between a user's claims
+    pub stake_decimals: u8,         // Decimals of stake_mint
+    pub reward_decimals: u8,        // Decimals of reward_mint
+    pub rewards_paused: bool,       // Emission frozen (stake and unstake stay open)
+    pub whitelist_enabled: bool,    // Only wallets with a Whitelist entry may stake
+    pub max_bonus_multiplier: u16,  // Cap on the effective reward multiplier (0 = uncapped)
+    pub version: u8,                // Schema version (see POOL_VERSION)
+    pub max_unstake_per_tx: u64,    // Cap on underlying withdrawn per unstake (0 = unlimited)
+    pub low_balance_threshold: u64, // Reward vault balance that counts as low (0 = never)
+    pub low_reward_balance: bool,   // Reward vault was below the threshold after the last claim
+    pub boost_multiplier: u16,      // Primary emission multiplier inside the boost window (0 = none)
+    pub boost_start: i64,           // Boost window start
+    pub boost_end: i64,             // Boost window end
+    pub pending_pool_authority: Pubkey, // Proposed pool authority awaiting acceptance
+    pub use_slot_based: bool,       // Primary reward accrues per slot instead of per second
+    pub reward_per_slot: u64,       // Primary reward rate for slot-based pools
+    pub last_update_slot: u64,      // Slot of the last slot-based accrual
+    pub performance_fee_bps: u16,   // Share of each reward claim routed to fee_recipient (bps)
+    pub fee_recipient: Pubkey,      // Owner of the reward account that receives the performance fee
+    pub reward_emission_cap: u64,   // Lifetime ceiling on total_reward_distributed (0 = uncapped)
+}
+
+impl Pool {
+    /// Replace the pool's lock tiers
+    pub fn set_lock_tiers(&mut self, tiers: &[LockTier]) -> Result<()> {
+        require!(
+            !tiers.is_empty() && tiers.len() <= MAX_LOCK_TIERS,
+            ErrorCode::InvalidLockTier
+        );
+        for tier in tiers {
+            require!(
+                tier.bonus_percentage <= u16::MAX - BPS_SCALE as u16,
+                ErrorCode::InvalidLockTier
+            );
+            require!(tier.duration <= MAX_LOCK_DURATION, ErrorCode::InvalidLockDuration);
+        }
+
+        self.lock_tiers = [LockTier::default(); MAX_LOCK_TIERS];
+        self.lock_tiers[..tiers.len()].copy_from_slice(tiers);
+        self.lock_tier_count = tiers.len() as u8;
+        Ok(())
+    }
+
+    /// Look up a configured lock tier
+    pub fn lock_tier(&self, index: u8) -> Result<LockTier> {
+        require!(index < self.lock_tier_count, ErrorCode::InvalidLockTier);
+        Ok(self.lock_tiers[index as usize])
+    }
+
+    /// Boost primary emission to `multiplier` (10000 = 1x) over [start, end);
+    /// a zero multiplier disables the boost
+    pub fn set_reward_boost(&mut self, multiplier: u16, start: i64, end: i64) -> Result<()> {
+        require!(
+            multiplier == 0 || multiplier as u64 >= BPS_SCALE,
+            ErrorCode::InvalidBonusMultiplier
+        );
+        require!(multiplier == 0 || end > start, ErrorCode::InvalidRewardWindow);
+        self.boost_multiplier = multiplier;
+        self.boost_start = start;
+        self.boost_end = end;
+        Ok(())
+    }
+
+    /// Seconds of [from, to) inside the boost window
+    pub fn boosted_seconds(&self, from: i64, to: i64) -> u64 {
+        if self.boost_multiplier == 0 {
+            return 0;
+        }
+        let start = from.max(self.boost_start);
+        let end = to.min(self.boost_end);
+        elapsed_since(end, start)
+    }
+
+    /// Set the emission window (`end == 0` emits indefinitely)
+    pub fn set_reward_window(&mut self, start: i64, end: i64) -> Result<()> {
+        require!(end == 0 || end > start, ErrorCode::InvalidRewardWindow);
+        self.reward_start_time = start;
+        self.reward_end_time = end;
+        Ok(())
+    }
+
+    /// Configured extra reward streams
+    pub fn active_reward_streams(&self) -> &[RewardStream] {
+        &self.reward_streams[..self.reward_stream_count as usize]
+    }
+
+    /// Replace the TVL reward curve (thresholds must strictly increase)
+    pub fn set_reward_curve(&mut self, breakpoints: &[RewardBreakpoint]) -> Result<()> {
+        require!(
+            breakpoints.len() <= MAX_REWARD_BREAKPOINTS,
+            ErrorCode::InvalidRewardCurve
+        );
+        require!(
+            breakpoints.windows(2).all(|pair| pair[0].tvl_threshold < pair[1].tvl_threshold),
+            ErrorCode::InvalidRewardCurve
+        );
 
-    /// CHECK: User wallet
-    #[account(mut)]
-    pub user_wallet: AccountInfo<'info>,
+        self.reward_curve = [RewardBreakpoint::default(); MAX_REWARD_BREAKPOINTS];
+        self.reward_curve[..breakpoints.len()].copy_from_slice(breakpoints);
+        self.reward_curve_len = breakpoints.len() as u8;
+        Ok(())
+    }
 
-    pub authority: Signer<'info>,
-}
+    /// Primary reward rate at the current `total_staked`
+    pub fn current_reward_rate(&self) -> u64 {
+        self.reward_curve[..self.reward_curve_len as usize]
+            .iter()
+            .rev()
+            .find(|breakpoint| self.total_staked >= breakpoint.tvl_threshold)
+            .map_or(self.reward_per_second, |breakpoint| breakpoint.reward_per_second)
+    }
 
-// ============ Data Structures ============
+    /// Convert a reward-mint base-unit amount into stake-mint base units
+    pub fn reward_to_stake_units(&self, amount: u128) -> Result<u128> {
+        let stake_scale = 10u128
+            .checked_pow(self.stake_decimals as u32)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let reward_scale = 10u128
+            .checked_pow(self.reward_decimals as u32)
+            .ok_or(ErrorCode::MathOverflow)?;
+        amount
+            .checked_mul(stake_scale)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(reward_scale)
+            .ok_or(ErrorCode::MathOverflow.into())
+    }
+}
 
-#[account]
-pub struct GlobalState {
-    pub bump: u8,
-    pub authority: Pubkey,
-    pub pool_count: u64,
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct LockTier {
+    pub duration: u64,         // Lock duration in seconds (0 = flexible)
+    pub bonus_percentage: u16, // Bonus percentage (5000 = 50%)
 }
 
-impl GlobalState {
-    pub const LEN: usize = 8 + 32 + 8; // bump + authority + pool_count
+impl LockTier {
+    /// Lock end for a stake made at `now` (0 for flexible tiers)
+    pub fn lock_end(&self, now: i64) -> Result<i64> {
+        if self.duration == 0 {
+            return Ok(0);
+        }
+        let duration = i64::try_from(self.duration).map_err(|_| ErrorCode::LockEndOverflow)?;
+        Ok(now.checked_add(duration).ok_or(ErrorCode::LockEndOverflow)?)
+    }
+
+    /// Position multiplier granted by this tier (10000 = 1x)
+    pub fn multiplier(&self) -> Result<u16> {
+        Ok((BPS_SCALE as u16)
+            .checked_add(self.bonus_percentage)
+            .ok_or(ErrorCode::MathOverflow)?)
+    }
 }
 
-#[account]
-pub struct Pool {
-    pub bump: u8,
-    pub pool_id: [u8; 32],          // Pool identifier (e.g., "wave", "wealth")
-    pub stake_mint: Pubkey,         // Token being staked
-    pub lst_mint: Pubkey,           // Liquid Staking Token mint
-    pub reward_mint: Pubkey,        // Reward token mint
-    pub reward_per_second: u64,     // Base reward rate
-    pub lock_duration: u64,         // Lock duration in seconds (2592000 = 30 days)
-    pub lock_bonus_percentage: u16, // Bonus percentage (5000 = 50%)
-    pub total_staked: u64,          // Total tokens staked in pool
-    pub total_reward_distributed: u64, // Total rewards distributed
-    pub last_update_timestamp: i64, // Last time pool was updated
-    pub authority: Pubkey,          // Pool authority
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct RewardBreakpoint {
+    pub tvl_threshold: u64,     // Minimum total_staked for this rate
+    pub reward_per_second: u64, // Primary reward rate at or above the threshold
 }
 
-impl Pool {
-    pub const LEN: usize = 8 + // discriminator
-        32 + // pool_id
-        32 + // stake_mint
-        32 + // lst_mint
-        32 + // reward_mint
-        8 +  // reward_per_second
-        8 +  // lock_duration
-        2 +  // lock_bonus_percentage
-        8 +  // total_staked
-        8 +  // total_reward_distributed
-        8 +  // last_update_timestamp
-        32;  // authority
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct RewardStream {
+    pub reward_mint: Pubkey,        // Extra reward token mint
+    pub reward_vault: Pubkey,       // Vault paying out this stream
+    pub reward_per_second: u64,     // Emission rate
+    pub acc_reward_per_share: u128, // Accumulated rewards per unit of weighted stake (scaled by SHARE_PRECISION)
 }
 
 #[account]
+#[derive(Default, InitSpace)]
 pub struct User {
     pub bump: u8,
     pub amount: u64,                  // Amount staked
-    pub lock_type: u8,                // 0 = flexible, 1 = locked
-    pub lock_start_timestamp: i64,    // Lock start time
+    pub lock_type: u8,                // Lock tier index the stake was made in
+    pub lock_start_timestamp: i64,    // Lock start time (stake start for flexible positions)
     pub lock_end_timestamp: i64,      // Lock end time
     pub bonus_multiplier: u16,        // Reward multiplier (10000 = 1x)
     pub last_reward_claim_timestamp: i64, // Last reward claim
+    pub reward_debt: u128,            // amount * acc_reward_per_share at last settlement
+    pub unclaimed_rewards: u64,       // Rewards settled but not yet paid out
+    pub version: u8,                  // Schema version (see USER_VERSION)
+    pub reward_streams: [UserRewardStream; MAX_REWARD_STREAMS], // Per-stream settlement state
+    pub pool: Pubkey,                 // Pool this position belongs to
+    pub unstake_request: UnstakeRequest, // Queued withdrawal awaiting cooldown
+    pub nft_boost_bps: u16,           // Applied NFT boost (bps)
+    pub nft_boost_expires_at: i64,    // NFT boost stops counting at this time
+    pub stake_snapshots: [StakeSnapshot; MAX_STAKE_SNAPSHOTS], // Ring buffer of voting snapshots
+    pub snapshot_count: u64,          // Snapshots taken (next slot = count % MAX_STAKE_SNAPSHOTS)
+    pub referrer: Pubkey,             // Wallet earning the referral bonus (default = none)
+    pub weighted_stake: u64,          // This position's share of pool.total_weighted_stake
+    pub auto_renew: bool,             // Roll the lock into a new term when it lapses
+    pub label: [u8; 16],              // Owner-chosen tag (informational)
+    pub owner: Pubkey,                // Wallet the position belongs to (the PDA seed)
+    pub lifetime_rewards_claimed: u64, // Primary rewards claimed or compounded, ever
+    pub claim_delegate: Option<Pubkey>, // Wallet allowed to claim on the owner's behalf
+    pub position_index: u8,           // Which of the owner's positions in this pool (PDA seed)
+    pub lst_minted: u64,              // LST issued to this position and not yet burned or moved
+    pub nft_boost_mint: Pubkey,       // NFT behind the current boost (default = none)
+}
+
+/// Use of an NFT to boost a position in a pool, held until the boost expires
+#[account]
+#[derive(InitSpace)]
+pub struct NftBoostLock {
+    pub bump: u8,
+    pub pool: Pubkey,
+    pub mint: Pubkey,    // Boosting NFT
+    pub user: Pubkey,    // Position the NFT is boosting
+    pub expires_at: i64, // Another position may use the NFT from this time
+}
+
+/// Permission for `wallet` to stake in a whitelisted pool
+#[account]
+#[derive(InitSpace)]
+pub struct Whitelist {
+    pub bump: u8,
+    pub pool: Pubkey,
+    pub wallet: Pubkey,
+}
+
+/// Return value of `get_global_stats`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GlobalStats {
+    pub total_value_locked: u128,        // Sum of every pool's total_staked
+    pub total_rewards_distributed: u128, // Primary rewards paid out across all pools
+}
+
+/// Return value of `get_unlock_info`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnlockInfo {
+    pub is_locked: bool,
+    pub seconds_remaining: u64, // 0 once unlocked or for flexible positions
+    pub unlock_timestamp: i64,  // Lock end (0 = flexible)
+}
+
+/// Return value of `preview_unstake`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnstakePreview {
+    pub principal_returned: u64, // Stake tokens paid out, net of any penalty
+    pub penalty: u64,            // Withheld for exiting inside the lock
+    pub pending_rewards: u64,    // Rewards kept, net of the performance fee (0 when an early exit forfeits them)
+}
+
+/// Primary rewards split into the performance fee and the position's share
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RewardSettlement {
+    pub gross: u64,           // Everything the position has earned
+    pub performance_fee: u64, // Withheld for the pool's fee recipient
+    pub net: u64,             // Paid out or restaked for the position
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct StakeSnapshot {
+    pub snapshot_id: u64, // Caller-chosen governance snapshot id
+    pub weight: u64,      // Stake scaled by the lock multiplier
+    pub timestamp: i64,   // When the snapshot was taken
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct UnstakeRequest {
+    pub amount: u64,       // Underlying tokens queued for withdrawal
+    pub available_at: i64, // Earliest complete_unstake time
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct UserRewardStream {
+    pub reward_debt: u128,      // amount * stream acc_reward_per_share at last settlement
+    pub unclaimed_rewards: u64, // Stream rewards settled but not yet paid out
 }
 
-impl User {
-    pub const LEN: usize = 8 + // discriminator
-        1 + // bump
-        8 + // amount
-        1 + // lock_type
-        8 + // lock_start_timestamp
-        8 + // lock_end_timestamp
-        2 + // bonus_multiplier
-        8;  // last_reward_claim_timestamp
+// ============ Events ============
+
+/// Emitted for every deposit into a position
+#[event]
+pub struct StakeEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,     // User (position) account
+    pub amount: u64,      // Tokens credited to the position
+    pub lst_minted: u64,
+    pub lock_type: u8,
+    pub label: [u8; 16],  // Position label at the time of the deposit
+}
+
+/// Emitted for every primary reward claim (including compounds)
+#[event]
+pub struct ClaimEvent {
+    pub pool: Pubkey,
+    pub user: Pubkey,                  // User (position) account
+    pub amount: u64,                   // Primary rewards claimed
+    pub lifetime_rewards_claimed: u64, // Position total after this claim
+}
+
+/// Emitted when a claim first leaves the reward vault below `low_balance_threshold`
+#[event]
+pub struct LowRewardBalanceEvent {
+    pub pool: Pubkey,
+    pub vault_balance: u64, // Reward vault balance after the claim
+    pub threshold: u64,
 }
 
 // ============ Error Codes ============
@@ -645,4 +5691,814 @@ pub enum ErrorCode {
     InvalidMint,
     #[msg("Invalid token program")]
     InvalidTokenProgram,
+    #[msg("Pool vault balance is insufficient")]
+    InsufficientVaultBalance,
+    #[msg("Reward vault has insufficient funds")]
+    InsufficientRewardFunds,
+    #[msg("Signer is not authorized for this action")]
+    Unauthorized,
+    #[msg("Invalid lock tier")]
+    InvalidLockTier,
+    #[msg("Basis points value exceeds 10000")]
+    InvalidBasisPoints,
+    #[msg("Cannot shorten an active lock")]
+    CannotShortenLock,
+    #[msg("Compounding requires the reward mint to equal the stake mint")]
+    CompoundMintMismatch,
+    #[msg("Deposit is below the pool minimum stake")]
+    BelowMinimumStake,
+    #[msg("Deposit would exceed the pool's staking cap")]
+    PoolCapExceeded,
+    #[msg("Pool already has the maximum number of reward streams")]
+    TooManyRewardStreams,
+    #[msg("Reward stream accounts are missing or invalid")]
+    InvalidRewardStream,
+    #[msg("User account does not belong to this pool")]
+    PoolMismatch,
+    #[msg("Unstake cooldown has not elapsed")]
+    CooldownNotElapsed,
+    #[msg("Pool requires unstaking through the cooldown queue")]
+    CooldownRequired,
+    #[msg("Reward end time must be after the start time")]
+    InvalidRewardWindow,
+    #[msg("Protocol is in emergency shutdown")]
+    ProtocolShutdown,
+    #[msg("Pool has reached its maximum number of users")]
+    MaxUsersReached,
+    #[msg("Treasury account is missing or does not match the pool")]
+    InvalidTreasury,
+    #[msg("Batch accounts are missing or do not match their pool")]
+    InvalidBatchAccounts,
+    #[msg("Requested amount exceeds the available rewards")]
+    InsufficientRewards,
+    #[msg("Signer does not hold an NFT from the boost collection")]
+    NotNftHolder,
+    #[msg("Stakers can still claim from the reward vault")]
+    RewardsStillOwed,
+    #[msg("Pool still holds stake or reward funds")]
+    PoolNotEmpty,
+    #[msg("Lock duration exceeds the maximum")]
+    InvalidLockDuration,
+    #[msg("Invalid or missing referrer account")]
+    InvalidReferrer,
+    #[msg("Reward rate is zero; pass allow_zero_reward for a reward-free pool")]
+    ZeroRewardRate,
+    #[msg("Maximum number of pools reached")]
+    MaxPoolsReached,
+    #[msg("Reward curve thresholds must strictly increase")]
+    InvalidRewardCurve,
+    #[msg("Claimed too soon after the previous claim")]
+    ClaimTooSoon,
+    #[msg("Stake, reward and LST tokens cannot be rescued")]
+    CannotRescueProtocolToken,
+    #[msg("Global state is already initialized")]
+    AlreadyInitialized,
+    #[msg("Nothing would be staked after fees")]
+    StakeTooSmallAfterFee,
+    #[msg("Position has no active lock")]
+    NoActiveLock,
+    #[msg("Batch account and argument counts differ")]
+    BatchLengthMismatch,
+    #[msg("Lock end timestamp overflows")]
+    LockEndOverflow,
+    #[msg("Wallet is not whitelisted for this pool")]
+    NotWhitelisted,
+    #[msg("Bonus multiplier cap must be 0 or at least 1x")]
+    InvalidBonusMultiplier,
+    #[msg("User account still holds stake or rewards")]
+    UserNotEmpty,
+    #[msg("Unstake exceeds the pool's per-transaction limit")]
+    UnstakeExceedsLimit,
+    #[msg("Queued unstake is already withdrawable")]
+    UnstakeRequestMatured,
+    #[msg("A pool with this id already exists")]
+    PoolAlreadyExists,
+    #[msg("Every user account of the pool must be supplied")]
+    IncompleteUserSet,
+    #[msg("Added funding does not cover the extended reward window")]
+    InsufficientFundingForExtension,
+    #[msg("Fee recipient account does not belong to the pool's fee recipient")]
+    InvalidFeeRecipient,
+    #[msg("NFT is already boosting another position")]
+    NftBoostInUse,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool_with_stake(total_staked: u64, reward_per_second: u64) -> Pool {
+        Pool {
+            total_staked,
+            total_weighted_stake: total_staked,
+            reward_per_second,
+            ..Default::default()
+        }
+    }
+
+    fn user_with_stake(pool: &Pool, amount: u64) -> User {
+        User {
+            amount,
+            bonus_multiplier: BPS_SCALE as u16,
+            reward_debt: reward_debt_for(pool, amount).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rewards_are_proportional_to_stake() {
+        let mut pool = pool_with_stake(300_000_000, 1_000_000);
+        let small = user_with_stake(&pool, 100_000_000);
+        let large = user_with_stake(&pool, 200_000_000);
+
+        update_pool_rewards(&mut pool, 60).unwrap();
+
+        let small_rewards = pending_rewards(&pool, &small).unwrap();
+        let large_rewards = pending_rewards(&pool, &large).unwrap();
+        assert_eq!(small_rewards, 20_000_000);
+        assert_eq!(large_rewards, 40_000_000);
+        assert_eq!(small_rewards + large_rewards, 60 * 1_000_000);
+    }
+
+    #[test]
+    fn settled_user_has_no_pending_rewards() {
+        let mut pool = pool_with_stake(100_000_000, 1_000_000);
+        update_pool_rewards(&mut pool, 100).unwrap();
+        let user = user_with_stake(&pool, 100_000_000);
+
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 0);
+    }
+
+    #[test]
+    fn rewards_only_accrue_inside_window() {
+        let mut pool = pool_with_stake(100_000_000, 1_000_000);
+        pool.set_reward_window(100, 200).unwrap();
+        let user = user_with_stake(&pool, 100_000_000);
+
+        update_pool_rewards(&mut pool, 50).unwrap();
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 0);
+
+        update_pool_rewards(&mut pool, 500).unwrap();
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 100 * 1_000_000);
+    }
+
+    #[test]
+    fn boost_applies_only_inside_its_window() {
+        let mut pool = pool_with_stake(100_000_000, 1_000_000);
+        pool.set_reward_boost(20_000, 10, 20).unwrap();
+        let user = user_with_stake(&pool, 100_000_000);
+
+        // [0, 15): 10 plain seconds and 5 doubled ones
+        update_pool_rewards(&mut pool, 15).unwrap();
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 20 * 1_000_000);
+
+        // [15, 30): 5 doubled seconds, then 10 plain ones
+        update_pool_rewards(&mut pool, 30).unwrap();
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 40 * 1_000_000);
+        assert_eq!(pool.total_reward_distributed, 40 * 1_000_000);
+
+        assert!(pool.set_reward_boost(5_000, 10, 20).is_err());
+        assert!(pool.set_reward_boost(20_000, 20, 20).is_err());
+    }
+
+    #[test]
+    fn slot_based_pools_accrue_per_slot() {
+        let mut pool = pool_with_stake(100_000_000, 1_000_000);
+        pool.use_slot_based = true;
+        pool.reward_per_slot = 400_000;
+        pool.last_update_slot = 1_000;
+        let user = user_with_stake(&pool, 100_000_000);
+
+        // 25 slots pass while the clock moves 100 seconds; only slots count
+        accrue_rewards(&mut pool, 100, 1_025).unwrap();
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 25 * 400_000);
+        assert_eq!(pool.last_update_slot, 1_025);
+
+        // A stale slot accrues nothing
+        accrue_rewards(&mut pool, 200, 1_020).unwrap();
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 25 * 400_000);
+        assert_eq!(pool.last_update_slot, 1_025);
+    }
+
+    #[test]
+    fn emissions_halt_at_the_cap() {
+        let mut pool = pool_with_stake(100_000_000, 1_000_000);
+        pool.reward_emission_cap = 25_500_000;
+        let user = user_with_stake(&pool, 100_000_000);
+
+        accrue_rewards(&mut pool, 20, 0).unwrap();
+        assert_eq!(pool.total_reward_distributed, 20_000_000);
+
+        // The next window would emit 20 more; only the 5.5 left under the cap accrue
+        accrue_rewards(&mut pool, 40, 0).unwrap();
+        assert_eq!(pool.total_reward_distributed, 25_500_000);
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 25_500_000);
+
+        // Further elapsed time accrues nothing
+        let acc = pool.acc_reward_per_share;
+        accrue_rewards(&mut pool, 1_000, 0).unwrap();
+        assert_eq!(pool.acc_reward_per_share, acc);
+        assert_eq!(pool.total_reward_distributed, 25_500_000);
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 25_500_000);
+    }
+
+    #[test]
+    fn longer_flexible_stakes_earn_loyalty_bonus() {
+        let mut pool = pool_with_stake(200_000_000, 1_000_000);
+        pool.loyalty_bonus_per_period_bps = 100;
+        pool.loyalty_period_seconds = 30 * 86_400;
+        pool.max_loyalty_bonus_bps = 1_000;
+
+        let mut veteran = User {
+            lock_start_timestamp: 0,
+            last_reward_claim_timestamp: 120 * 86_400,
+            ..user_with_stake(&pool, 100_000_000)
+        };
+        let newcomer = User {
+            lock_start_timestamp: 90 * 86_400,
+            last_reward_claim_timestamp: 120 * 86_400,
+            ..user_with_stake(&pool, 100_000_000)
+        };
+
+        // Four periods (+4%) against one period (+1%) as of the last settlement;
+        // the veteran's fifth period only counts once it is settled again
+        update_pool_rewards(&mut pool, 150 * 86_400).unwrap();
+        assert_eq!(effective_multiplier(&pool, &veteran), 10_400);
+        assert_eq!(effective_multiplier(&pool, &newcomer), 10_100);
+        assert!(pending_rewards(&pool, &veteran).unwrap() > pending_rewards(&pool, &newcomer).unwrap());
+
+        // The bonus stops growing at the cap
+        veteran.last_reward_claim_timestamp = 3_650 * 86_400;
+        update_pool_rewards(&mut pool, 3_651 * 86_400).unwrap();
+        assert_eq!(effective_multiplier(&pool, &veteran), 11_000);
+    }
+
+    #[test]
+    fn nft_boost_raises_rate_until_expiry() {
+        let mut pool = pool_with_stake(200_000_000, 1_000_000);
+        let plain = user_with_stake(&pool, 100_000_000);
+        let boosted = User {
+            nft_boost_bps: 5_000,
+            nft_boost_expires_at: 100,
+            ..user_with_stake(&pool, 100_000_000)
+        };
+
+        update_pool_rewards(&mut pool, 50).unwrap();
+        assert_eq!(effective_multiplier(&pool, &boosted), 15_000);
+        assert_eq!(
+            pending_rewards(&pool, &boosted).unwrap(),
+            pending_rewards(&pool, &plain).unwrap() * 3 / 2
+        );
+
+        // Past the expiry the boost no longer counts
+        update_pool_rewards(&mut pool, 150).unwrap();
+        assert_eq!(effective_multiplier(&pool, &boosted), 10_000);
+    }
+
+    #[test]
+    fn stacked_bonuses_are_clamped_to_the_pool_cap() {
+        let mut pool = pool_with_stake(200_000_000, 1_000_000);
+        let stacked = User {
+            bonus_multiplier: 15_000,
+            lock_end_timestamp: 1_000,
+            nft_boost_bps: 5_000,
+            nft_boost_expires_at: 1_000,
+            ..user_with_stake(&pool, 100_000_000)
+        };
+
+        update_pool_rewards(&mut pool, 50).unwrap();
+        assert_eq!(effective_multiplier(&pool, &stacked), 20_000);
+
+        pool.max_bonus_multiplier = 17_500;
+        assert_eq!(effective_multiplier(&pool, &stacked), 17_500);
+
+        let plain = user_with_stake(&pool, 100_000_000);
+        assert_eq!(
+            pending_rewards(&pool, &stacked).unwrap(),
+            pending_rewards(&pool, &plain).unwrap() * 7 / 4
+        );
+
+        // A tier multiplier that does not fit in u16 is an error, not a wrap
+        let oversized = LockTier { duration: 0, bonus_percentage: u16::MAX };
+        assert_eq!(oversized.multiplier().unwrap_err(), ErrorCode::MathOverflow.into());
+    }
+
+    #[test]
+    fn ten_years_at_a_high_rate_does_not_overflow() {
+        let elapsed = 10 * SECONDS_PER_YEAR;
+        let mut pool = pool_with_stake(1_000_000_000_000_000, 1_000_000_000_000);
+        let user = user_with_stake(&pool, 1_000_000);
+
+        update_pool_rewards(&mut pool, elapsed as i64).unwrap();
+
+        // Total emissions exceed u64, so the counter saturates
+        assert_eq!(pool.total_reward_distributed, u64::MAX);
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), elapsed * 1_000_000_000);
+    }
+
+    #[test]
+    fn lock_durations_are_capped() {
+        let mut pool = Pool::default();
+        let longest = LockTier { duration: MAX_LOCK_DURATION, bonus_percentage: 0 };
+        let too_long = LockTier { duration: MAX_LOCK_DURATION + 1, bonus_percentage: 0 };
+
+        assert!(pool.set_lock_tiers(&[longest]).is_ok());
+        assert_eq!(
+            pool.set_lock_tiers(&[too_long]).unwrap_err(),
+            ErrorCode::InvalidLockDuration.into()
+        );
+
+        // The longest lock ends exactly at the boundary, and an end past
+        // i64::MAX errors instead of wrapping
+        let now = 1_700_000_000;
+        assert_eq!(longest.lock_end(now).unwrap(), now + MAX_LOCK_DURATION as i64);
+        assert!(longest.lock_end(i64::MAX).is_err());
+    }
+
+    #[test]
+    fn lock_end_overflow_is_reported() {
+        let longest = LockTier { duration: MAX_LOCK_DURATION, bonus_percentage: 0 };
+        let last_safe = i64::MAX - MAX_LOCK_DURATION as i64;
+
+        assert_eq!(longest.lock_end(last_safe).unwrap(), i64::MAX);
+        assert_eq!(
+            longest.lock_end(last_safe + 1).unwrap_err(),
+            ErrorCode::LockEndOverflow.into()
+        );
+
+        // Durations beyond i64 are rejected rather than wrapped by a cast
+        let unrepresentable = LockTier { duration: u64::MAX, bonus_percentage: 0 };
+        assert_eq!(unrepresentable.lock_end(0).unwrap_err(), ErrorCode::LockEndOverflow.into());
+
+        // Unconfigured tiers are errors, not flexible
+        let pool = Pool { lock_tier_count: 1, ..Default::default() };
+        assert_eq!(pool.lock_tier(1).unwrap_err(), ErrorCode::InvalidLockTier.into());
+    }
+
+    #[test]
+    fn share_precision_beats_a_bps_share_scale() {
+        let mut pool = pool_with_stake(30_000, 1_000_000);
+        let user = user_with_stake(&pool, 7);
+        update_pool_rewards(&mut pool, 60).unwrap();
+
+        // A share rounded to basis points (7 / 30000 -> 2 bps) loses 1/7 of the rewards
+        let emitted = 60 * 1_000_000;
+        let bps_share = 7 * BPS_SCALE / 30_000;
+        assert_eq!(emitted * bps_share / BPS_SCALE, 12_000);
+
+        // SHARE_PRECISION keeps the exact 7 / 30000 share
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 14_000);
+    }
+
+    #[test]
+    fn tiny_stakes_accumulate_fractional_rewards() {
+        let mut pool = pool_with_stake(1_000_000_000, 1_000);
+        let user = user_with_stake(&pool, 1);
+
+        // Paying each hourly settlement as a whole-unit share rounds to zero every time
+        let hourly_share = user.amount * pool.reward_per_second * 3_600 / pool.total_staked;
+        assert_eq!(hourly_share, 0);
+
+        // The accumulator keeps the fractions, so twelve days of hourly
+        // updates add up to a claimable unit
+        for hour in 1..=288 {
+            update_pool_rewards(&mut pool, hour * 3_600).unwrap();
+        }
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 1);
+    }
+
+    /// Deterministic xorshift so the property tests need no extra crate
+    fn next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn payouts_never_exceed_emissions() {
+        for seed in 1..=200u64 {
+            let mut rng = seed;
+            let mut pool = Pool {
+                reward_per_second: 1 + next_random(&mut rng) % 1_000_000,
+                ..Default::default()
+            };
+            let mut users: Vec<User> = Vec::new();
+            let mut paid = 0u128;
+            let mut now = 0i64;
+
+            for _ in 0..50 {
+                now += 1 + (next_random(&mut rng) % 600) as i64;
+                update_pool_rewards(&mut pool, now).unwrap();
+
+                if users.is_empty() || next_random(&mut rng) % 3 == 0 {
+                    // A new staker joins at whatever the accumulator is now
+                    let amount = 1 + next_random(&mut rng) % 1_000_000_000;
+                    pool.total_staked += amount;
+                    pool.total_weighted_stake += amount;
+                    users.push(user_with_stake(&pool, amount));
+                } else {
+                    // An existing staker claims and resets its debt
+                    let index = (next_random(&mut rng) % users.len() as u64) as usize;
+                    let user = &mut users[index];
+                    paid += pending_rewards(&pool, user).unwrap() as u128;
+                    user.reward_debt = reward_debt_for(&pool, user.amount).unwrap();
+                }
+            }
+            for user in &users {
+                paid += pending_rewards(&pool, user).unwrap() as u128;
+            }
+
+            assert!(
+                paid <= pool.total_reward_distributed as u128,
+                "seed {}: paid {} of {} emitted",
+                seed,
+                paid,
+                pool.total_reward_distributed
+            );
+        }
+    }
+
+    #[test]
+    fn redemptions_never_exceed_the_vault() {
+        for seed in 1..=200u64 {
+            let mut rng = seed;
+            let mut pool = Pool {
+                total_staked: 1 + next_random(&mut rng) % 1_000_000_000_000,
+                ..Default::default()
+            };
+            let mut lst_supply = 1 + next_random(&mut rng) % 1_000_000_000_000;
+            let mut vault = pool.total_staked;
+            let deposited = vault;
+            let mut redeemed = 0u64;
+
+            while lst_supply > 0 {
+                let lst_amount = 1 + next_random(&mut rng) % lst_supply;
+                let amount = redeemable_amount(&pool, lst_supply, vault, lst_amount).unwrap();
+                assert!(amount <= vault, "seed {}: {} owed from {}", seed, amount, vault);
+
+                redeemed += amount;
+                vault -= amount;
+                pool.total_staked -= amount;
+                lst_supply -= lst_amount;
+            }
+
+            // Truncation leaves dust for the last redeemer, never a shortfall
+            assert_eq!(redeemed, deposited, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn split_lst_follows_the_exchange_rate() {
+        // 3 LST back 4 tokens: 1 token is worth 0.75 LST
+        let pool = Pool { total_staked: 4_000_000, ..Default::default() };
+        assert_eq!(lst_for_amount_rounded_up(&pool, 3_000_000, 1_000_000).unwrap(), 750_000);
+        assert_eq!(lst_for_amount_rounded_up(&pool, 3_000_000, 1).unwrap(), 1);
+
+        // Before any LST exists the rate is 1:1
+        assert_eq!(lst_for_amount_rounded_up(&pool, 0, 5).unwrap(), 5);
+    }
+
+    #[test]
+    fn performance_fee_comes_out_of_gross_rewards() {
+        let mut pool = Pool::default();
+        let free = settle_rewards(&pool, 1_000_001).unwrap();
+        assert_eq!((free.performance_fee, free.net), (0, 1_000_001));
+
+        // The fee rounds down, so the position keeps any dust
+        pool.performance_fee_bps = 2_000;
+        let settlement = settle_rewards(&pool, 1_000_001).unwrap();
+        assert_eq!(settlement.performance_fee, 200_000);
+        assert_eq!(settlement.net, 800_001);
+        assert_eq!(settlement.gross, settlement.performance_fee + settlement.net);
+    }
+
+    #[test]
+    fn dominant_staker_rewards_are_capped() {
+        let mut pool = pool_with_stake(1_000_000_000, 1_000_000);
+        pool.max_reward_share_bps = 5_000;
+        let whale = user_with_stake(&pool, 900_000_000);
+        let minnow = user_with_stake(&pool, 100_000_000);
+
+        update_pool_rewards(&mut pool, 10).unwrap();
+
+        // 90% of the stake earns only the 50% cap; the rest stays in the vault
+        assert_eq!(pending_rewards(&pool, &whale).unwrap(), 5_000_000);
+        assert_eq!(pending_rewards(&pool, &minnow).unwrap(), 1_000_000);
+    }
+
+    #[test]
+    fn expired_locks_earn_the_base_rate() {
+        let mut pool = pool_with_stake(100_000_000, 1_000_000);
+        let mut user = User {
+            bonus_multiplier: 15_000,
+            lock_end_timestamp: 100,
+            ..user_with_stake(&pool, 100_000_000)
+        };
+
+        // Half the span is locked (1.5x), half is past expiry (1x)
+        update_pool_rewards(&mut pool, 200).unwrap();
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 250_000_000);
+
+        // Once settled past expiry, the position earns exactly 1x
+        user.reward_debt = reward_debt_for(&pool, user.amount).unwrap();
+        user.last_reward_claim_timestamp = 200;
+        update_pool_rewards(&mut pool, 300).unwrap();
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 100_000_000);
+    }
+
+    #[test]
+    fn runway_divides_the_vault_by_the_rate() {
+        let mut pool = pool_with_stake(100_000_000, 1_000_000);
+        assert_eq!(reward_runway(&pool, 3_600_000_000), 3_600);
+        assert_eq!(reward_runway(&pool, 999_999), 0);
+
+        pool.reward_per_second = 0;
+        assert_eq!(reward_runway(&pool, 3_600_000_000), 0);
+
+        pool.use_slot_based = true;
+        pool.reward_per_slot = 400_000;
+        assert_eq!(reward_runway(&pool, 3_600_000_000), 9_000);
+    }
+
+    #[test]
+    fn weighted_stake_is_rebuilt_from_positions() {
+        let mut pool = pool_with_stake(300_000_000, 1_000_000);
+        let users = [
+            User { weighted_stake: 100_000_000, ..Default::default() },
+            User { weighted_stake: 250_000_000, ..Default::default() },
+        ];
+
+        // Corrupt the denominator, then rebuild it
+        pool.total_weighted_stake = 7;
+        pool.total_weighted_stake = total_user_weight(&users).unwrap();
+        assert_eq!(pool.total_weighted_stake, 350_000_000);
+        assert_eq!(total_user_weight(&[]).unwrap(), 0);
+
+        let overflowing = [
+            User { weighted_stake: u64::MAX, ..Default::default() },
+            User { weighted_stake: 1, ..Default::default() },
+        ];
+        assert!(total_user_weight(&overflowing).is_err());
+    }
+
+    #[test]
+    fn elapsed_since_never_goes_negative() {
+        assert_eq!(elapsed_since(100, 40), 60);
+        assert_eq!(elapsed_since(100, 100), 0);
+        assert_eq!(elapsed_since(40, 100), 0);
+        assert_eq!(elapsed_since(i64::MIN, i64::MAX), 0);
+        assert_eq!(elapsed_since(i64::MAX, i64::MIN), i64::MAX as u64);
+    }
+
+    #[test]
+    fn unlock_countdown_tracks_the_clock() {
+        let locked = User { lock_end_timestamp: 1_000, ..Default::default() };
+        for now in [0, 400, 999] {
+            assert_eq!(
+                unlock_info(&locked, now),
+                UnlockInfo {
+                    is_locked: true,
+                    seconds_remaining: (1_000 - now) as u64,
+                    unlock_timestamp: 1_000,
+                }
+            );
+        }
+        for now in [1_000, 5_000] {
+            let info = unlock_info(&locked, now);
+            assert!(!info.is_locked);
+            assert_eq!(info.seconds_remaining, 0);
+        }
+
+        let flexible = User::default();
+        assert_eq!(
+            unlock_info(&flexible, 500),
+            UnlockInfo { is_locked: false, seconds_remaining: 0, unlock_timestamp: 0 }
+        );
+    }
+
+    #[test]
+    fn unstake_preview_depends_on_the_lock() {
+        let mut pool = pool_with_stake(100_000_000, 1_000_000);
+        pool.early_withdraw_penalty_bps = 1_000;
+        let mut user = user_with_stake(&pool, 100_000_000);
+        user.lock_end_timestamp = 1_000;
+        update_pool_rewards(&mut pool, 100).unwrap();
+
+        // Inside the lock: emergency terms
+        assert_eq!(
+            unstake_preview(&pool, &user, 100_000_000, 100_000_000, 50_000_000, 500).unwrap(),
+            UnstakePreview { principal_returned: 45_000_000, penalty: 5_000_000, pending_rewards: 0 }
+        );
+
+        // Once it lapses: the full share plus everything earned
+        assert_eq!(
+            unstake_preview(&pool, &user, 100_000_000, 100_000_000, 50_000_000, 1_000).unwrap(),
+            UnstakePreview { principal_returned: 50_000_000, penalty: 0, pending_rewards: 100_000_000 }
+        );
+
+        // The rewards shown are what the user keeps after the performance fee
+        pool.performance_fee_bps = 2_000;
+        assert_eq!(
+            unstake_preview(&pool, &user, 100_000_000, 100_000_000, 50_000_000, 1_000).unwrap(),
+            UnstakePreview { principal_returned: 50_000_000, penalty: 0, pending_rewards: 80_000_000 }
+        );
+    }
+
+    #[test]
+    fn redemptions_are_limited_to_the_positions_own_lst() {
+        let pool = pool_with_stake(100_000_000, 1_000_000);
+        let user = User {
+            lst_minted: 3_000_000,
+            ..user_with_stake(&pool, 10_000_000)
+        };
+
+        // Partial exits release principal pro rata, rounded against the user
+        assert_eq!(principal_for_lst(&user, 1_000_000).unwrap(), 3_333_334);
+        assert_eq!(principal_for_lst(&user, 3_000_000).unwrap(), 10_000_000);
+
+        // LST held in the wallet for another position cannot redeem this one
+        assert_eq!(
+            principal_for_lst(&user, 3_000_001).unwrap_err(),
+            ErrorCode::InsufficientStake.into()
+        );
+    }
+
+    #[test]
+    fn reward_rate_follows_the_tvl_curve() {
+        let mut pool = pool_with_stake(100_000_000, 1_000_000);
+        pool.set_reward_curve(&[RewardBreakpoint {
+            tvl_threshold: 1_000_000_000,
+            reward_per_second: 2_000_000,
+        }])
+        .unwrap();
+
+        // Below the threshold the base rate applies
+        update_pool_rewards(&mut pool, 10).unwrap();
+        assert_eq!(pool.total_reward_distributed, 10_000_000);
+
+        // A large deposit crosses it; only later seconds use the higher rate
+        pool.total_staked += 900_000_000;
+        update_pool_rewards(&mut pool, 20).unwrap();
+        assert_eq!(pool.total_reward_distributed, 10_000_000 + 20_000_000);
+
+        assert!(pool
+            .set_reward_curve(&[
+                RewardBreakpoint { tvl_threshold: 5, reward_per_second: 1 },
+                RewardBreakpoint { tvl_threshold: 5, reward_per_second: 2 },
+            ])
+            .is_err());
+    }
+
+    #[test]
+    fn weighted_stake_splits_the_full_emission() {
+        let mut pool = pool_with_stake(400_000_000, 1_000_000);
+        pool.total_weighted_stake = 0;
+        pool.loyalty_bonus_per_period_bps = 2_500;
+        pool.loyalty_period_seconds = 100;
+        pool.max_loyalty_bonus_bps = 5_000;
+
+        let mut flexible = user_with_stake(&pool, 100_000_000);
+        let mut locked = User {
+            bonus_multiplier: 15_000,
+            lock_end_timestamp: 1_000,
+            ..user_with_stake(&pool, 100_000_000)
+        };
+        // Two loyalty periods in by the time it is weighed
+        let mut loyal = User {
+            lock_start_timestamp: -200,
+            ..user_with_stake(&pool, 100_000_000)
+        };
+        let mut boosted = User {
+            nft_boost_bps: 10_000,
+            nft_boost_expires_at: 1_000,
+            ..user_with_stake(&pool, 100_000_000)
+        };
+        for user in [&mut flexible, &mut locked, &mut loyal, &mut boosted] {
+            reweigh_stake(&mut pool, user, 0).unwrap();
+        }
+        assert_eq!(pool.total_weighted_stake, 600_000_000);
+
+        update_pool_rewards(&mut pool, 60).unwrap();
+
+        let rewards: Vec<u64> = [&flexible, &locked, &loyal, &boosted]
+            .iter()
+            .map(|user| pending_rewards(&pool, user).unwrap())
+            .collect();
+        assert_eq!(rewards, vec![10_000_000, 15_000_000, 15_000_000, 20_000_000]);
+        assert_eq!(rewards.iter().sum::<u64>(), 60 * 1_000_000);
+
+        // Once the lock and the boost lapse their bonuses leave the
+        // denominator, while the flexible position's loyalty bonus joins it
+        reweigh_stake(&mut pool, &mut locked, 1_000).unwrap();
+        reweigh_stake(&mut pool, &mut boosted, 1_000).unwrap();
+        reweigh_stake(&mut pool, &mut flexible, 1_000).unwrap();
+        assert_eq!(pool.total_weighted_stake, 500_000_000);
+    }
+
+    #[test]
+    fn reward_units_convert_across_decimals() {
+        let mut pool = pool_with_stake(10_000_000, 1_000_000_000);
+        pool.stake_decimals = 6;
+        pool.reward_decimals = 9;
+
+        // Payouts are in reward-mint units and need no conversion
+        let user = user_with_stake(&pool, 10_000_000);
+        update_pool_rewards(&mut pool, 10).unwrap();
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 10_000_000_000);
+
+        // 10 reward tokens are worth 10 stake tokens
+        assert_eq!(pool.reward_to_stake_units(10_000_000_000).unwrap(), 10_000_000);
+        pool.reward_decimals = 3;
+        assert_eq!(pool.reward_to_stake_units(10_000).unwrap(), 10_000_000);
+    }
+
+    #[test]
+    fn auto_renewing_locks_roll_forward_and_keep_the_bonus() {
+        let mut user = User {
+            amount: 100,
+            bonus_multiplier: 15_000,
+            lock_start_timestamp: 0,
+            lock_end_timestamp: 100,
+            auto_renew: true,
+            ..Default::default()
+        };
+
+        renew_lock(&mut user, 99).unwrap();
+        assert_eq!(user.lock_end_timestamp, 100);
+
+        // Two and a half terms later the lock is in its fourth term
+        renew_lock(&mut user, 250).unwrap();
+        assert_eq!((user.lock_start_timestamp, user.lock_end_timestamp), (200, 300));
+        let pool = Pool { last_update_timestamp: 1_000, ..Default::default() };
+        assert_eq!(stake_weight(&pool, &user, 250).unwrap(), 150);
+        assert_eq!(effective_multiplier(&pool, &user), 15_000);
+    }
+
+    #[test]
+    fn accrual_renews_the_lock_and_includes_banked_rewards() {
+        let mut pool = pool_with_stake(100_000_000, 1_000_000);
+        pool.performance_fee_bps = 1_000;
+        let mut user = User {
+            lock_end_timestamp: 5,
+            auto_renew: true,
+            unclaimed_rewards: 500,
+            ..user_with_stake(&pool, 100_000_000)
+        };
+
+        let gross = accrue_user_rewards(&mut pool, &mut user, 10).unwrap();
+        assert_eq!(gross, 10 * 1_000_000 + 500);
+        assert_eq!((user.lock_start_timestamp, user.lock_end_timestamp), (10, 15));
+
+        let settlement = settle_rewards(&pool, gross).unwrap();
+        assert_eq!(settlement.performance_fee, 1_000_050);
+        assert_eq!(settlement.net, 9_000_450);
+    }
+
+    #[test]
+    fn paused_windows_accrue_nothing() {
+        let mut pool = pool_with_stake(100_000_000, 1_000_000);
+        let user = user_with_stake(&pool, 100_000_000);
+
+        update_pool_rewards(&mut pool, 10).unwrap();
+        pool.rewards_paused = true;
+        update_pool_rewards(&mut pool, 50).unwrap();
+        assert_eq!(pool.last_update_timestamp, 50);
+        pool.rewards_paused = false;
+        update_pool_rewards(&mut pool, 60).unwrap();
+
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 20 * 1_000_000);
+    }
+
+    #[test]
+    fn empty_periods_are_not_back_paid() {
+        let mut pool = pool_with_stake(0, 1_000_000);
+
+        update_pool_rewards(&mut pool, 100).unwrap();
+        assert_eq!(pool.acc_reward_per_share, 0);
+        assert_eq!(pool.last_update_timestamp, 100);
+
+        // The first staker arrives after the empty span
+        pool.total_staked = 100_000_000;
+        pool.total_weighted_stake = 100_000_000;
+        let user = user_with_stake(&pool, 100_000_000);
+        update_pool_rewards(&mut pool, 110).unwrap();
+
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 10 * 1_000_000);
+    }
+
+    #[test]
+    fn future_timestamps_accrue_zero_rewards() {
+        let mut pool = pool_with_stake(100_000_000, 1_000_000);
+        pool.last_update_timestamp = 1_000;
+        let mut user = user_with_stake(&pool, 100_000_000);
+        user.last_reward_claim_timestamp = 1_005;
+
+        // Clock is behind both stored timestamps
+        update_pool_rewards(&mut pool, 990).unwrap();
+
+        assert_eq!(pool.acc_reward_per_share, 0);
+        assert_eq!(pool.last_update_timestamp, 1_000);
+        assert_eq!(pending_rewards(&pool, &user).unwrap(), 0);
+    }
 }